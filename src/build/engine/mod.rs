@@ -33,7 +33,7 @@ impl Default for Engine<'_> {
 }
 
 impl Engine<'_> {
-    pub fn get_post(&self) -> TemplateRef {
+    pub fn get_post(&self) -> TemplateRef<'_> {
         self.engine.get_template("post").unwrap()
     }
 }
@@ -2,7 +2,9 @@ mod recent_post;
 mod summarise;
 
 use crate::{
-    build::{engine::render_index::recent_post::RecentPost, read_dir, BuildError, BuildRes},
+    build::{
+        engine::render_index::recent_post::RecentPost, read_dir, BuildError, BuildOptions, BuildRes,
+    },
     Config,
 };
 
@@ -10,6 +12,7 @@ use super::Engine;
 
 use std::{fs::File, path::Path};
 
+use chrono::Utc;
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -20,18 +23,26 @@ pub struct IndexTemplate<'a> {
 }
 
 impl Engine<'_> {
-    /// Renders the `index.html` template, including summarised forms of the 5 most recent posts
+    /// Renders the `index.html` template, including summarised forms of the 5 most recent posts,
+    /// leaving out those that `options` doesn't include
     ///
     /// # Panics
     ///
     /// If the `index.html` template has not been loaded into the engine beforehand using
     /// [`Engine::load_templates`], a panic will occur
-    pub fn render_index(&mut self, config: &Config, dir: impl AsRef<Path>) -> BuildRes {
+    pub fn render_index(
+        &mut self,
+        config: &Config,
+        dir: impl AsRef<Path>,
+        options: &BuildOptions,
+    ) -> BuildRes {
         let dir = dir.as_ref();
         let posts_dir = dir.join("posts");
         let mut recent_posts = read_dir(&posts_dir, &[])?
-            .map(|entry| RecentPost::from_path(entry.path()))
+            .map(|entry| RecentPost::from_path(entry.path(), config))
             .collect::<Result<Vec<RecentPost>, BuildError>>()?;
+        let now = Utc::now();
+        recent_posts.retain(|post| options.includes(post.frontmatter.status(now)));
 
         recent_posts.sort_unstable_by(|post1, post2| {
             post1
@@ -43,7 +54,7 @@ impl Engine<'_> {
             .into_iter()
             .rev()
             .take(5)
-            .map(RecentPost::hydrate)
+            .map(|post| post.hydrate(config))
             .collect::<Result<_, BuildError>>()?;
 
         let template = self.engine.get_template("index").unwrap();
@@ -1,11 +1,11 @@
 use std::{fs, path::PathBuf};
 
 use serde::Serialize;
-use snafu::ResultExt;
 
 use crate::{
-    build::{BuildError, ParseFrontmatterSnafu},
+    build::{permalink::Permalink, BuildError},
     frontmatter::Frontmatter,
+    Config,
 };
 
 use super::summarise::summarise_content;
@@ -19,53 +19,36 @@ pub struct RecentPost {
     #[serde(flatten)]
     pub frontmatter: Frontmatter,
     md_content: String,
+    /// The byte offset of the content in `md_content`, after the frontmatter
+    #[serde(skip)]
+    content_start: usize,
     summary: Option<String>,
 }
 
 impl RecentPost {
     /// Returns the post at the path, with an empty summary
-    pub fn from_path(path: PathBuf) -> Result<Self, BuildError> {
+    ///
+    /// Any defaults used in the frontmatter aren't warned about, as they are when the post itself is
+    /// built.
+    pub fn from_path(path: PathBuf, config: &Config) -> Result<Self, BuildError> {
         let md_content = fs::read_to_string(&path).map_err(|err| (err, path.clone()))?;
-        let mut sections = md_content.split("---");
-        let frontmatter_source = sections
-            .nth(1)
-            .ok_or_else(|| BuildError::MissingFrontmatter { path: path.clone() })?;
-        let frontmatter = serde_yaml::from_str(frontmatter_source)
-            .with_context(|_| ParseFrontmatterSnafu { path: path.clone() })?;
+        let (frontmatter, content_start, _) = Frontmatter::parse(&md_content, &path, config)?;
 
         Ok(Self {
             path,
             url: None,
             frontmatter,
             md_content,
+            content_start,
             summary: None,
         })
     }
 
     /// Fill in the `url` and `summary` fields of the post
-    pub fn hydrate(self) -> Result<Self, BuildError> {
-        let filename = self
-            .path
-            .file_stem()
-            .expect("missing filename")
-            .to_string_lossy()
-            .to_string()
-            + ".html";
-        let url = Some(
-            PathBuf::from(".")
-                .join("posts")
-                .join(filename)
-                .to_string_lossy()
-                .to_string(),
-        );
-        let content_markdown =
-            self.md_content
-                .split("---")
-                .nth(2)
-                .ok_or_else(|| BuildError::MissingFrontmatter {
-                    path: self.path.clone(),
-                })?;
-        let summary = Some(summarise_content(content_markdown));
+    pub fn hydrate(self, config: &Config) -> Result<Self, BuildError> {
+        let permalink = Permalink::new(&config.permalink, &self.frontmatter, &self.path)?;
+        let url = Some(format!("./{}", permalink.url()));
+        let summary = Some(summarise_content(&self.md_content[self.content_start..]));
         Ok(RecentPost {
             url,
             summary,
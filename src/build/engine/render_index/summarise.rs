@@ -17,13 +17,12 @@ pub(super) fn summarise_content(content_markdown: &str) -> String {
                 tags_stack.pop();
                 end_tag(&tag, &mut buffer, &tags_stack);
             }
-            Event::Text(content) => {
+            Event::Text(content)
                 if !tags_stack
                     .iter()
-                    .any(|tag| matches!(tag, Tag::Strikethrough))
-                {
-                    buffer.push_str(&content)
-                }
+                    .any(|tag| matches!(tag, Tag::Strikethrough)) =>
+            {
+                buffer.push_str(&content)
             }
             Event::Code(content) => buffer.push_str(&content),
             Event::SoftBreak => buffer.push(' '),
@@ -65,11 +64,7 @@ fn start_tag(tag: &Tag, buffer: &mut String, tags_stack: &mut [Tag]) {
 fn end_tag(tag: &Tag, buffer: &mut String, tags_stack: &[Tag]) {
     match tag {
         Tag::Paragraph | Tag::Heading(..) => buffer.push(' '),
-        Tag::CodeBlock(_) => {
-            if buffer.ends_with(' ') {
-                buffer.push(' ');
-            }
-        }
+        Tag::CodeBlock(_) if buffer.ends_with(' ') => buffer.push(' '),
         Tag::List(_) => {
             let is_sublist = tags_stack.iter().any(|tag| matches!(tag, Tag::List(_)));
             if !is_sublist {
@@ -0,0 +1,75 @@
+//! Listing the posts of a project along with whether they are published
+
+use std::{fs, path::Path};
+
+use chrono::Utc;
+
+use crate::frontmatter::Frontmatter;
+
+use super::{read_config, read_dir, BuildRes};
+
+/// Print every post of the project in `dir` in chronological order, with its status, or only those
+/// that are scheduled if `scheduled` is set
+pub fn list_posts(dir: impl AsRef<Path>, scheduled: bool) -> BuildRes {
+    let dir = dir.as_ref();
+    let config = read_config(dir)?;
+    let now = Utc::now();
+
+    let mut posts = Vec::new();
+    for post in read_dir(&dir.join("posts"), &[])? {
+        let path = post.path();
+        let source = fs::read_to_string(&path).map_err(|err| (err, path.clone()))?;
+        let (frontmatter, ..) = Frontmatter::parse(&source, &path, &config)?;
+        let status = frontmatter.status(now);
+        if !scheduled || status.scheduled {
+            posts.push((frontmatter, status, path));
+        }
+    }
+    posts.sort_unstable_by_key(|(frontmatter, ..)| frontmatter.timestamp);
+
+    // A post can be held back for more than one reason, all of which are listed
+    let statuses = posts
+        .iter()
+        .map(|(_, status, _)| {
+            let reasons = [
+                (status.draft, "draft", "33"),
+                (status.scheduled, "scheduled", "36"),
+                (status.expired, "expired", "31"),
+            ];
+            if status.is_published() {
+                vec![("published", "32")]
+            } else {
+                reasons
+                    .into_iter()
+                    .filter(|(held_back, ..)| *held_back)
+                    .map(|(_, reason, colour)| (reason, colour))
+                    .collect()
+            }
+        })
+        .collect::<Vec<_>>();
+    let width = |reasons: &[(&str, &str)]| {
+        reasons
+            .iter()
+            .map(|(reason, _)| reason.len() + 2)
+            .sum::<usize>()
+            - 2
+    };
+    let max_width = statuses.iter().map(|reasons| width(reasons)).max();
+
+    for ((frontmatter, _, path), reasons) in posts.iter().zip(&statuses) {
+        let status = reasons
+            .iter()
+            .map(|(reason, colour)| format!("\x1b[{colour};1m{reason}\x1b[0m"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "{status}{:padding$}  {}  {}  ({})",
+            "",
+            frontmatter.timestamp.format("%Y-%m-%d %H:%M %:z"),
+            frontmatter.title,
+            path.strip_prefix(dir).unwrap_or(path).display(),
+            padding = max_width.unwrap_or_default() - width(reasons),
+        );
+    }
+    Ok(())
+}
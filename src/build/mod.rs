@@ -1,22 +1,36 @@
 //! Building a Hyde project
 
 mod engine;
+mod list;
+mod permalink;
 mod posts;
 
 use std::{
+    collections::{BTreeSet, HashSet},
     ffi::OsStr,
+    fmt::Display,
     fs::{self, DirEntry, Metadata},
-    io::{self, ErrorKind},
+    io,
     path::{Path, PathBuf},
 };
 
 use snafu::{ResultExt, Snafu};
 
+pub use self::list::list_posts;
+
 use crate::{
-    build::{engine::Engine, posts::compile_posts},
-    Config,
+    build::{
+        engine::Engine,
+        posts::{compile_posts, theme_stylesheet},
+    },
+    frontmatter::Status,
+    Config, HighlightTheme,
 };
 
+/// The line that starts the stylesheet for highlighted code that the build writes, which is how it
+/// tells its own stylesheet apart from one written by `hyde highlight-css` or copied from the theme
+const HIGHLIGHT_CSS_MARKER: &str = "/* Written by `hyde build` from the `[highlight] theme` */\n";
+
 /// An error that arose while building a Hyde project, this is a very broad categorisation,
 /// involving user-input-induced errors and I/O errors
 #[derive(Debug, Snafu)]
@@ -27,7 +41,21 @@ pub enum BuildError {
 
     /// Failed to parse the `hyde.toml` config file
     #[snafu(display("Failed to parse the `hyde.toml` config file: {source}"))]
-    ParseConfig { source: toml::de::Error },
+    ParseConfig {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    /// Failed to parse a helix theme for highlighting code
+    #[snafu(display("Failed to parse the highlight theme at '{}': {source}", path.display()))]
+    ParseTheme {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    /// A helix theme for highlighting code inherits from itself, directly or through others
+    #[snafu(display("The highlight theme at '{}' inherits from itself", path.display()))]
+    ThemeCycle { path: PathBuf },
 
     /// Failed to compile a template in the project's theme directory
     #[snafu(display("Failed to compile a template: {source}"))]
@@ -59,6 +87,95 @@ pub enum BuildError {
         path: PathBuf,
     },
 
+    /// Failed to parse the TOML frontmatter of a post
+    #[snafu(display("Failed to parse the post at '{}': {source}", path.display()))]
+    ParseTomlFrontmatter {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    /// Failed to parse the JSON frontmatter of a post
+    #[snafu(display("Failed to parse the post at '{}': {source}", path.display()))]
+    ParseJsonFrontmatter {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    /// The `permalink` pattern in the config contains an unknown placeholder
+    #[snafu(display("Unknown placeholder ':{placeholder}' in the permalink pattern '{pattern}', only ':year', ':month', ':day' and ':slug' are supported"))]
+    PermalinkPlaceholder {
+        placeholder: String,
+        pattern: String,
+    },
+
+    /// The `permalink` pattern has a `.` or `..` part, so posts could be written anywhere
+    #[snafu(display("The permalink pattern '{pattern}' must not have a '.' or '..' part"))]
+    PermalinkTraversal { pattern: String },
+
+    /// Two posts have the same permalink, so one would overwrite the other
+    #[snafu(display("The posts at '{}' and '{}' have the same URL '{url}', give one of them a different `slug`", path.display(), other.display()))]
+    DuplicatePermalink {
+        url: String,
+        path: PathBuf,
+        other: PathBuf,
+    },
+
+    /// A post's slug isn't a single part of a path, so the post could be written anywhere
+    #[snafu(display("The post at '{}' has the slug '{slug}', which must not be empty, '.' or '..', or contain '/' or '\\'", path.display()))]
+    InvalidSlug { slug: String, path: PathBuf },
+
+    /// The `permalink` pattern would put a post in place of the site's index
+    #[snafu(display("The permalink pattern '{pattern}' gives the post at '{}' the URL of the site's index", path.display()))]
+    PermalinkIndex { pattern: String, path: PathBuf },
+
+    /// A post doesn't give its language, and there is no default
+    #[snafu(display("The post at '{}' has no `language`, and `default_language` isn't set in the config", path.display()))]
+    MissingLanguage { path: PathBuf },
+
+    /// A reference to an equation in a post used a label that isn't given to any equation
+    #[snafu(display("Unresolved equation label '{label}' in the post at '{}'", path.display()))]
+    UnresolvedLabel { label: String, path: PathBuf },
+
+    /// KaTeX failed to render a piece of maths in a post
+    #[snafu(display("Failed to render the maths at '{}:{line}:{column}': {message}", path.display()))]
+    RenderMaths {
+        message: String,
+        path: PathBuf,
+        line: usize,
+        column: usize,
+    },
+
+    /// Failed to load a tree-sitter grammar from the grammar directory
+    #[snafu(display("Failed to load the grammar at '{}': {source}", path.display()))]
+    LoadGrammar {
+        source: libloading::Error,
+        path: PathBuf,
+    },
+
+    /// A grammar in the grammar directory was generated by an incompatible version of tree-sitter
+    #[snafu(display(
+        "The grammar at '{}' has ABI version {version}, but only versions {}-{} are supported",
+        path.display(),
+        tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION,
+        tree_sitter::LANGUAGE_VERSION
+    ))]
+    GrammarVersion { version: usize, path: PathBuf },
+
+    /// Failed to parse the queries of a grammar in the grammar directory
+    #[snafu(display("Invalid queries for the grammar at '{}': {source}", path.display()))]
+    GrammarQuery {
+        source: tree_sitter::QueryError,
+        path: PathBuf,
+    },
+
+    /// Tree-sitter failed to highlight a piece of code in a post
+    #[snafu(display("Failed to highlight the '{lang}' code in the post at '{}': {source}", path.display()))]
+    Highlight {
+        source: tree_sitter_highlight::Error,
+        lang: String,
+        path: PathBuf,
+    },
+
     /// A miscellaneous I/O error
     #[snafu(display("IO error at '{}': {source}", path.display()))]
     MiscIO { source: io::Error, path: PathBuf },
@@ -70,9 +187,86 @@ impl From<(io::Error, PathBuf)> for BuildError {
     }
 }
 
+impl BuildError {
+    /// The path of the file that caused the error, if the error can be pinned down to one
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            BuildError::ParseConfig { path, .. }
+            | BuildError::ParseTheme { path, .. }
+            | BuildError::ThemeCycle { path }
+            | BuildError::IndexTemplate { path }
+            | BuildError::PostTemplate { path }
+            | BuildError::MissingFrontmatter { path }
+            | BuildError::RenderPost { path, .. }
+            | BuildError::ParseFrontmatter { path, .. }
+            | BuildError::ParseTomlFrontmatter { path, .. }
+            | BuildError::ParseJsonFrontmatter { path, .. }
+            | BuildError::MissingLanguage { path }
+            | BuildError::DuplicatePermalink { path, .. }
+            | BuildError::InvalidSlug { path, .. }
+            | BuildError::PermalinkIndex { path, .. }
+            | BuildError::UnresolvedLabel { path, .. }
+            | BuildError::RenderMaths { path, .. }
+            | BuildError::LoadGrammar { path, .. }
+            | BuildError::GrammarVersion { path, .. }
+            | BuildError::GrammarQuery { path, .. }
+            | BuildError::Highlight { path, .. }
+            | BuildError::MiscIO { path, .. } => Some(path),
+            BuildError::MissingConfig
+            | BuildError::CompileTemplate { .. }
+            | BuildError::PermalinkPlaceholder { .. }
+            | BuildError::PermalinkTraversal { .. } => None,
+        }
+    }
+
+    /// The (one-indexed) line of [`BuildError::path`] that caused the error, if it is known
+    pub fn line(&self) -> Option<usize> {
+        let line = match self {
+            BuildError::ParseConfig { source, .. } | BuildError::ParseTheme { source, .. } => {
+                source.line_col().map(|(line, _)| line + 1)
+            }
+            // The frontmatter starts on the same line as the opening `---`, so its lines line up
+            // with those of the post
+            BuildError::ParseFrontmatter { source, .. } => {
+                source.location().map(|location| location.line())
+            }
+            BuildError::ParseTomlFrontmatter { source, .. } => {
+                source.line_col().map(|(line, _)| line + 1)
+            }
+            // JSON frontmatter starts at the very start of the post
+            BuildError::ParseJsonFrontmatter { source, .. } => Some(source.line()),
+            BuildError::RenderMaths { line, .. } => Some(*line),
+            _ => None,
+        };
+        // serde_json gives errors that aren't at any position in particular (e.g. the wrong type
+        // of value) the line 0
+        line.filter(|&line| line > 0)
+    }
+}
+
 /// The [`Result`] of trying to build a Hyde project
 pub type BuildRes = Result<(), BuildError>;
 
+/// Options for a single build, as opposed to those in the project's config
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BuildOptions {
+    /// Whether to build posts marked as drafts
+    pub drafts: bool,
+    /// Whether to build posts with timestamps in the future, which are otherwise left until a build
+    /// after that time
+    pub future: bool,
+}
+
+impl BuildOptions {
+    /// Whether a post with the given status is built, which needs every reason that it is held
+    /// back for to be overridden
+    ///
+    /// Expired posts are never built.
+    fn includes(&self, status: Status) -> bool {
+        (!status.draft || self.drafts) && (!status.scheduled || self.future) && !status.expired
+    }
+}
+
 /// Builds the Hyde project in a given directory
 ///
 /// # Summary
@@ -80,16 +274,9 @@ pub type BuildRes = Result<(), BuildError>;
 /// Read from the `hyde.toml` config file, create the `static/` directory for statically generated output,
 /// copy over the auxiliary theme files, and compile all of the posts in the `posts/` directory into it,
 /// using the `templates/` from the theme specified in the config.
-pub fn build_proj(dir: impl AsRef<Path>) -> BuildRes {
+pub fn build_proj(dir: impl AsRef<Path>, options: &BuildOptions) -> BuildRes {
     let dir = dir.as_ref();
-    /* Read and parse the `hyde.toml` config */
-    let config_path = dir.join("hyde.toml");
-    if !config_path.exists() {
-        return Err(BuildError::MissingConfig);
-    }
-    let config_source =
-        fs::read_to_string(config_path.clone()).context(MiscIOSnafu { path: config_path })?;
-    let config: Config = toml::from_str(&config_source).context(ParseConfigSnafu)?;
+    let config = read_config(dir)?;
 
     /* Create the `static/` directory for statically generated output if it does not already exist */
     let static_dir = dir.join("static");
@@ -100,20 +287,54 @@ pub fn build_proj(dir: impl AsRef<Path>) -> BuildRes {
     /* Initialise the template engine and render the index page */
     let mut engine = Engine::default();
     engine.load_templates(&config)?;
-    engine.render_index(&config, dir)?;
+    engine.render_index(&config, dir, options)?;
+
+    /* Compile all posts in `posts/` into `static/`, at their permalinks */
+    let post_paths = compile_posts(&config, &engine, dir, options)?;
 
-    /* Remove any extra files in `static/` that do not exist in the project's theme dir */
-    compare_and_clean(
-        &static_dir,
-        &config.theme,
-        &["posts", "index.html"].map(OsStr::new),
-    )?;
+    /* Remove any extra files in `static/` that do not exist in the project's theme dir, other than
+     * the generated ones */
+    let mut generated = ["index.html", "highlight.css"].map(OsStr::new).to_vec();
+    let mut post_dirs = BTreeSet::new();
+    for path in &post_paths {
+        let mut components = path.strip_prefix(&static_dir).unwrap_or(path).components();
+        let top_level = components.next().expect("empty permalink").as_os_str();
+        generated.push(top_level);
+        if components.next().is_some() {
+            post_dirs.insert(top_level);
+        }
+    }
+    compare_and_clean(&static_dir, &config.theme, &generated)?;
+
+    /* Within the directories that posts are compiled into, also remove anything that no post
+     * compiles to any more, e.g. after the permalink pattern or a post's slug has changed */
+    let post_paths = post_paths.iter().collect::<HashSet<_>>();
+    for post_dir in post_dirs {
+        clean_post_dir(
+            &static_dir.join(post_dir),
+            &config.theme.join(post_dir),
+            &post_paths,
+        )?;
+    }
 
     /* Copy all entries other than `templates/` from the project's theme directory into `static/` */
     copy_entries(&config.theme, &static_dir, &[OsStr::new("templates")])?;
 
-    /* Compile all posts in `posts/` into `static/` */
-    compile_posts(&config, &engine, dir)?;
+    /* Generate the stylesheet for highlighted code from the configured helix theme, if any,
+     * otherwise remove the one that an earlier build generated, leaving any other alone */
+    let highlight_css_path = static_dir.join("highlight.css");
+    if let Some(theme) = &config.highlight.theme {
+        let css = format!("{HIGHLIGHT_CSS_MARKER}{}", highlight_css(dir, theme)?);
+        fs::write(&highlight_css_path, css).context(MiscIOSnafu {
+            path: highlight_css_path,
+        })?;
+    } else if fs::read_to_string(&highlight_css_path)
+        .is_ok_and(|css| css.starts_with(HIGHLIGHT_CSS_MARKER))
+    {
+        fs::remove_file(&highlight_css_path).context(MiscIOSnafu {
+            path: highlight_css_path,
+        })?;
+    }
 
     println!(
         "\x1b[32;1mSuccess\x1b[0m: Generated static site for project '{}'",
@@ -122,6 +343,32 @@ pub fn build_proj(dir: impl AsRef<Path>) -> BuildRes {
     Ok(())
 }
 
+/// Generates the stylesheet for highlighted code from the helix theme(s) in `theme`, whose paths
+/// are relative to the project directory `dir`
+pub fn highlight_css(dir: impl AsRef<Path>, theme: &HighlightTheme) -> Result<String, BuildError> {
+    theme_stylesheet(dir.as_ref(), theme)
+}
+
+/// Prints a warning about something that doesn't warrant failing the build
+pub(crate) fn warn(message: impl Display) {
+    eprintln!("\x1b[33;1mWarning\x1b[0m: {message}");
+}
+
+/// Reads and parses the `hyde.toml` config file of the Hyde project in a given directory
+pub fn read_config(dir: impl AsRef<Path>) -> Result<Config, BuildError> {
+    let config_path = dir.as_ref().join("hyde.toml");
+    if !config_path.exists() {
+        return Err(BuildError::MissingConfig);
+    }
+    let config_source = fs::read_to_string(config_path.clone()).context(MiscIOSnafu {
+        path: config_path.clone(),
+    })?;
+    let config: Config =
+        toml::from_str(&config_source).context(ParseConfigSnafu { path: config_path })?;
+    permalink::validate(&config.permalink)?;
+    Ok(config)
+}
+
 /// Compares two directories and cleans entries in the former that aren't present in the latter,
 /// excluding certain files.
 ///
@@ -155,7 +402,7 @@ fn compare_and_clean(
             /* ✨ a magical fourth thing ✨ */
             {
                 return Err((
-                    io::Error::new(ErrorKind::Other, "not a file, directory or symlink"),
+                    io::Error::other("not a file, directory or symlink"),
                     entry_path.to_path_buf(),
                 ));
             }
@@ -166,6 +413,37 @@ fn compare_and_clean(
     Ok(())
 }
 
+/// Removes every file in a directory that posts are compiled into that isn't in `keep` or
+/// `against`, along with any directories left empty by doing so
+///
+/// # Arguments
+///
+/// * `dir` - The directory being cleaned
+/// * `against` - The corresponding directory in the theme, whose files will be copied over
+/// * `keep` - The paths of the compiled posts
+fn clean_post_dir(
+    dir: &Path,
+    against: &Path,
+    keep: &HashSet<&PathBuf>,
+) -> Result<(), (io::Error, PathBuf)> {
+    for entry in read_dir(dir, &[])? {
+        let entry_path = entry.path();
+        let against_path = against.join(entry.file_name());
+        let file_type = entry.file_type().map_err(|err| (err, entry_path.clone()))?;
+
+        if file_type.is_dir() {
+            clean_post_dir(&entry_path, &against_path, keep)?;
+            let mut entries = fs::read_dir(&entry_path).map_err(|err| (err, entry_path.clone()))?;
+            if entries.next().is_none() {
+                fs::remove_dir(&entry_path).map_err(|err| (err, entry_path))?;
+            }
+        } else if !keep.contains(&entry_path) && !against_path.exists() {
+            fs::remove_file(&entry_path).map_err(|err| (err, entry_path))?;
+        }
+    }
+    Ok(())
+}
+
 /// Copies all entries from one directory to another, excluding certain entries.
 ///
 /// # Note
@@ -239,9 +517,9 @@ fn copy_entry(
     } else if entry_metadata.is_dir() {
         copy_entries(entry_path, dest, &[])
     } else {
-        return Err((
-            io::Error::new(ErrorKind::Other, "not a file, directory or symlink"),
+        Err((
+            io::Error::other("not a file, directory or symlink"),
             entry_path.to_path_buf(),
-        ));
+        ))
     }
 }
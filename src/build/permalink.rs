@@ -0,0 +1,183 @@
+//! Where each post ends up, as given by the `permalink` pattern in the config
+//!
+//! Both the URL of a post and the path that it is written to come from its [`Permalink`], so that
+//! the two always agree.
+
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::frontmatter::{filename_date, Frontmatter};
+
+use super::BuildError;
+
+static PLACEHOLDER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r":([a-z]+)").unwrap());
+
+/// The placeholders that a permalink pattern can contain, without their leading `:`
+const PLACEHOLDERS: &[&str] = &["year", "month", "day", "slug"];
+
+/// Check that a permalink pattern only contains known placeholders, and has no `.` or `..` parts
+/// that could take posts outside of `static/`
+pub fn validate(pattern: &str) -> Result<(), BuildError> {
+    if pattern
+        .split(['/', '\\'])
+        .any(|part| part == "." || part == "..")
+    {
+        return Err(BuildError::PermalinkTraversal {
+            pattern: pattern.to_string(),
+        });
+    }
+
+    for placeholder in PLACEHOLDER_REGEX.captures_iter(pattern) {
+        if !PLACEHOLDERS.contains(&&placeholder[1]) {
+            return Err(BuildError::PermalinkPlaceholder {
+                placeholder: placeholder[1].to_string(),
+                pattern: pattern.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The URL of a post, relative to the root of the site
+#[derive(Debug, Clone, PartialEq)]
+pub struct Permalink(String);
+
+impl Permalink {
+    /// Fill in a permalink pattern, e.g. `/:year/:month/:slug/`, for the post at `path`
+    ///
+    /// The slug is the post's `slug`, defaulting to its filename without any date that it starts
+    /// with (e.g. `hello` for `2023-10-24-hello.md`). It must be a single, non-empty part of a
+    /// path, so that the post can't end up outside of `static/`, and the URL mustn't be that of
+    /// the site's index.
+    pub fn new(pattern: &str, frontmatter: &Frontmatter, path: &Path) -> Result<Self, BuildError> {
+        let slug = frontmatter
+            .slug
+            .clone()
+            .unwrap_or_else(|| default_slug(path));
+        if slug.is_empty() || slug == "." || slug == ".." || slug.contains(['/', '\\']) {
+            return Err(BuildError::InvalidSlug {
+                slug,
+                path: path.to_path_buf(),
+            });
+        }
+
+        let timestamp = frontmatter.timestamp;
+        let url = PLACEHOLDER_REGEX.replace_all(pattern, |placeholder: &Captures| {
+            match &placeholder[1] {
+                "year" => timestamp.format("%Y").to_string(),
+                "month" => timestamp.format("%m").to_string(),
+                "day" => timestamp.format("%d").to_string(),
+                "slug" => slug.clone(),
+                // Unknown placeholders are rejected along with the config
+                _ => placeholder[0].to_string(),
+            }
+        });
+        // Empty and `.` parts don't change where the post goes, so they are dropped before the URL
+        // is compared with that of the index
+        let mut normalised = url
+            .split('/')
+            .filter(|part| !part.is_empty() && *part != ".")
+            .collect::<Vec<_>>()
+            .join("/");
+        if normalised.is_empty() || normalised.eq_ignore_ascii_case("index.html") {
+            return Err(BuildError::PermalinkIndex {
+                pattern: pattern.to_string(),
+                path: path.to_path_buf(),
+            });
+        }
+        if url.ends_with('/') {
+            normalised.push('/');
+        }
+        Ok(Self(normalised))
+    }
+
+    /// The URL of the post relative to the root of the site, e.g. `2023/10/hello/`
+    pub fn url(&self) -> &str {
+        &self.0
+    }
+
+    /// The path of the post's HTML in `static_dir`, which is an `index.html` if the URL is a
+    /// directory
+    pub fn output_path(&self, static_dir: &Path) -> PathBuf {
+        let path = static_dir.join(&self.0);
+        if self.0.ends_with('/') {
+            path.join("index.html")
+        } else {
+            path
+        }
+    }
+
+    /// The relative URL of the root of the site from the post, e.g. `../../../` for
+    /// `2023/10/hello/`, for linking to the rest of the site
+    pub fn root(&self) -> String {
+        "../".repeat(self.0.matches('/').count())
+    }
+}
+
+/// The filename of a post without its extension, or any `YYYY-MM-DD-` that it starts with
+fn default_slug(path: &Path) -> String {
+    match filename_date(path) {
+        Some((_, rest)) if rest.len() > 1 => rest[1..].to_string(),
+        _ => path
+            .file_stem()
+            .expect("missing filename")
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+#[test]
+fn fills_in_patterns() {
+    use chrono::DateTime;
+
+    let mut frontmatter = Frontmatter {
+        title: String::new(),
+        timestamp: DateTime::parse_from_rfc3339("2023-10-24T02:44:07Z").unwrap(),
+        language: String::new(),
+        tags: Vec::new(),
+        macros: Default::default(),
+        draft: false,
+        expires: None,
+        slug: None,
+        extra: Default::default(),
+    };
+    let path = Path::new("posts/2023-10-24-hello.md");
+
+    let permalink = Permalink::new("/posts/:slug.html", &frontmatter, path).unwrap();
+    assert_eq!(permalink.url(), "posts/hello.html");
+    assert_eq!(
+        permalink.output_path(Path::new("static")),
+        Path::new("static/posts/hello.html")
+    );
+    assert_eq!(permalink.root(), "../");
+
+    frontmatter.slug = Some("bonjour".to_string());
+    let permalink = Permalink::new("/:year/:month/:day/:slug/", &frontmatter, path).unwrap();
+    assert_eq!(permalink.url(), "2023/10/24/bonjour/");
+    assert_eq!(
+        permalink.output_path(Path::new("static")),
+        Path::new("static/2023/10/24/bonjour/index.html")
+    );
+    assert_eq!(permalink.root(), "../../../../");
+
+    for slug in ["", ".", "..", "../../x", "a/b", "a\\b"] {
+        frontmatter.slug = Some(slug.to_string());
+        assert!(Permalink::new("/posts/:slug.html", &frontmatter, path).is_err());
+    }
+    frontmatter.slug = None;
+    assert!(Permalink::new("/", &frontmatter, path).is_err());
+    assert!(Permalink::new("/index.html", &frontmatter, path).is_err());
+    assert!(Permalink::new("/./index.html", &frontmatter, path).is_err());
+    assert!(Permalink::new("//index.html", &frontmatter, path).is_err());
+    let permalink = Permalink::new("/posts//./:slug/", &frontmatter, path).unwrap();
+    assert_eq!(permalink.url(), "posts/hello/");
+
+    assert!(validate("/:year/:slug/").is_ok());
+    assert!(validate("/:title/").is_err());
+    assert!(validate("/../:slug.html").is_err());
+    assert!(validate("/posts/./:slug.html").is_err());
+    assert!(validate("/posts\\..\\:slug.html").is_err());
+    assert!(validate("/posts/..:slug.html").is_ok());
+}
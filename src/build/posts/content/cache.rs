@@ -0,0 +1,158 @@
+//! An on-disk cache of highlighted code, so that code which hasn't changed isn't highlighted again
+//! on every build
+//!
+//! Each entry is a file of HTML in the cache directory, named after the SHA-256 hash of everything
+//! that went into highlighting it. Nothing is ever invalidated, a changed input just misses the
+//! cache, and the entries that a full build doesn't use are evicted at the end of it. The directory
+//! can be deleted at any time.
+
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::build::warn;
+
+/// The directory of the cache, relative to the project directory
+pub const CACHE_DIR: &str = ".hyde-cache/highlight";
+
+pub struct Cache {
+    dir: PathBuf,
+    /// The entries that have been looked up or inserted during this build
+    used: RefCell<HashSet<Key>>,
+}
+
+impl Cache {
+    /// Open the cache of the project in `dir`, creating its directory if needed
+    ///
+    /// Returns `None`, after warning, if the directory can't be created, as the build can still go
+    /// ahead without it.
+    pub fn open(dir: &Path) -> Option<Self> {
+        let dir = dir.join(CACHE_DIR);
+        if let Err(err) = fs::create_dir_all(&dir) {
+            warn(format!(
+                "Failed to create the highlight cache at '{}', so code won't be cached: {err}",
+                dir.display()
+            ));
+            return None;
+        }
+        Some(Self {
+            dir,
+            used: RefCell::default(),
+        })
+    }
+
+    /// The HTML cached under a key, if there is any
+    pub fn get(&self, key: Key) -> Option<String> {
+        self.used.borrow_mut().insert(key);
+        fs::read_to_string(self.path(key)).ok()
+    }
+
+    /// Cache the HTML for a key, only warning if it can't be written
+    pub fn insert(&self, key: Key, html: &str) {
+        self.used.borrow_mut().insert(key);
+        let path = self.path(key);
+        if let Err(err) = fs::write(&path, html) {
+            warn(format!(
+                "Failed to write to the highlight cache at '{}': {err}",
+                path.display()
+            ));
+        }
+    }
+
+    /// Remove every entry that hasn't been used during this build, only warning if any can't be
+    ///
+    /// This is only worth doing after a build that compiled every post, as the entries of the
+    /// posts that were skipped would be evicted too.
+    pub fn evict_unused(&self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn(format!(
+                    "Failed to read the highlight cache at '{}': {err}",
+                    self.dir.display()
+                ));
+                return;
+            }
+        };
+        let used = self
+            .used
+            .borrow()
+            .iter()
+            .map(|key| self.path(*key))
+            .collect::<HashSet<_>>();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !used.contains(&path) {
+                if let Err(err) = fs::remove_file(&path) {
+                    warn(format!(
+                        "Failed to evict '{}' from the highlight cache: {err}",
+                        path.display()
+                    ));
+                }
+            }
+        }
+    }
+
+    fn path(&self, key: Key) -> PathBuf {
+        self.dir.join(format!("{key}.html"))
+    }
+}
+
+/// The key of an entry, a hash of its inputs that is stable across builds of Hyde
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key([u8; 32]);
+
+impl Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl AsRef<[u8]> for Key {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Hashes the inputs of an entry into its [`Key`]
+///
+/// Each input is prefixed with its length, so that different inputs can't run together into the
+/// same bytes.
+#[derive(Default)]
+pub struct KeyHasher(Sha256);
+
+impl KeyHasher {
+    pub fn input(&mut self, input: impl AsRef<[u8]>) -> &mut Self {
+        let input = input.as_ref();
+        self.0.update((input.len() as u64).to_le_bytes());
+        self.0.update(input);
+        self
+    }
+
+    pub fn finish(&mut self) -> Key {
+        Key(self.0.finalize_reset().into())
+    }
+}
+
+#[test]
+fn keys_are_stable() {
+    let key = KeyHasher::default()
+        .input("rust")
+        .input("fn main() {}")
+        .finish();
+    assert_eq!(
+        key.to_string(),
+        "64a9c8324c5ce6380c9d930d918968e4759a066ba45c72b44a3185517e293c3a"
+    );
+    let other = KeyHasher::default()
+        .input("rus")
+        .input("tfn main() {}")
+        .finish();
+    assert_ne!(key, other);
+}
@@ -0,0 +1,245 @@
+//! The info strings of fenced code blocks, e.g. ```` ```rs {3,5-7} title="src/main.rs" linenos ````,
+//! and rendering the code blocks that they describe
+
+use std::ops::RangeInclusive;
+
+use pulldown_cmark::escape::escape_html;
+
+use super::helix::InlineTheme;
+
+/// What the info string of a fenced code block asks for
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CodeBlockInfo {
+    /// The language of the code, empty if none is given
+    pub lang: String,
+    /// The (one-indexed) lines to highlight, e.g. `{3,5-7}`
+    pub highlighted_lines: Vec<RangeInclusive<usize>>,
+    /// A caption for the code block, e.g. `title="src/main.rs"`
+    pub title: Option<String>,
+    /// Whether to number the lines, given by `linenos`
+    pub line_numbers: bool,
+    /// Whether the code is a diff of code in the language, given by `diff-<lang>` or `<lang> diff`
+    pub diff: bool,
+}
+
+/// The kind of a line in a diff, given by its first character
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffLine {
+    Added,
+    Removed,
+    Unchanged,
+}
+
+impl CodeBlockInfo {
+    /// Parse an info string, where the language comes first and anything unrecognised is ignored
+    pub fn parse(info: &str) -> Self {
+        let mut code_block = Self::default();
+        let mut rest = info.trim();
+        while !rest.is_empty() {
+            let (token, tail) = next_token(rest);
+            rest = tail.trim_start();
+
+            if let Some(ranges) = token.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+                code_block
+                    .highlighted_lines
+                    .extend(ranges.split(',').filter_map(parse_range));
+            } else if let Some(title) = token.strip_prefix("title=") {
+                code_block.title = Some(title.trim_matches('"').to_string());
+            } else if token == "linenos" {
+                code_block.line_numbers = true;
+            } else if token == "diff" && !code_block.lang.is_empty() {
+                code_block.diff = true;
+            } else if code_block.lang.is_empty() && !token.contains('=') {
+                code_block.lang = token.to_string();
+            }
+        }
+        if let Some(lang) = code_block.lang.strip_prefix("diff-") {
+            code_block.lang = lang.to_string();
+            code_block.diff = true;
+        }
+        code_block
+    }
+
+    /// Wrap the (possibly highlighted) HTML of the code, in which every line ends with a newline
+    /// and no element spans more than one line, in the `<pre>` of the code block
+    ///
+    /// If the code is a diff, `diff` gives the kind of each line, see [`split_diff`]. `theme` is the
+    /// theme to style the code block with inline, if the code is highlighted with inline styles,
+    /// otherwise it is styled with classes.
+    pub fn to_html(
+        &self,
+        code_html: &str,
+        diff: &[DiffLine],
+        theme: Option<&InlineTheme>,
+    ) -> String {
+        let mut html = String::new();
+        if let Some(title) = &self.title {
+            html.push_str("<figure class=\"code-block\"><figcaption>");
+            escape_html(&mut html, title).unwrap();
+            html.push_str("</figcaption>");
+        }
+
+        html.push_str("<pre");
+        if let Some(pre_style) = theme.and_then(|theme| theme.pre_style.as_ref()) {
+            html.push_str(&format!(" style=\"{pre_style}\""));
+        }
+        html.push_str("><code");
+        if !self.lang.is_empty() {
+            html.push_str(" class=\"language-");
+            escape_html(&mut html, &self.lang).unwrap();
+            html.push('"');
+        }
+        html.push('>');
+        for (index, line) in code_html.lines().enumerate() {
+            let number = index + 1;
+            let highlighted = self
+                .highlighted_lines
+                .iter()
+                .any(|range| range.contains(&number));
+            let kind = diff.get(index).copied();
+            match theme {
+                Some(theme) => {
+                    // As with the classes, a highlighted line of a diff keeps the background of
+                    // its kind and is outlined instead
+                    let style = match kind {
+                        Some(kind @ (DiffLine::Added | DiffLine::Removed)) => {
+                            let mut style = theme.diff_line_styles[kind as usize].clone();
+                            if highlighted {
+                                style.push_str("; outline: 1px solid currentColor");
+                            }
+                            style
+                        }
+                        _ if highlighted => theme.highlighted_line_style.clone(),
+                        _ => String::new(),
+                    };
+                    if style.is_empty() {
+                        html.push_str("<span>");
+                    } else {
+                        html.push_str(&format!("<span style=\"{style}\">"));
+                    }
+                }
+                None => {
+                    html.push_str("<span class=\"line");
+                    if highlighted {
+                        html.push_str(" highlighted");
+                    }
+                    match kind {
+                        Some(DiffLine::Added) => html.push_str(" text-diff-add"),
+                        Some(DiffLine::Removed) => html.push_str(" text-diff-delete"),
+                        _ => (),
+                    }
+                    html.push_str("\">");
+                }
+            }
+            if self.line_numbers {
+                html.push_str(&match theme {
+                    Some(theme) => format!("<span style=\"{}\">", theme.line_number_style),
+                    None => "<span class=\"line-number\">".to_string(),
+                });
+                html.push_str(&format!("{number}</span>"));
+            }
+            // The markers aren't part of the code, so they are kept out of the text that gets
+            // copied, by the stylesheet's `::before` content, or by `user-select` inline
+            match (kind, theme) {
+                (Some(kind), Some(theme)) => {
+                    html.push_str(&format!(
+                        "<span style=\"{}\">",
+                        theme.diff_marker_styles[kind as usize]
+                    ));
+                    html.push(match kind {
+                        DiffLine::Added => '+',
+                        DiffLine::Removed => '-',
+                        DiffLine::Unchanged => ' ',
+                    });
+                    html.push_str("</span>");
+                }
+                (Some(_), None) => html.push_str("<span class=\"diff-marker\"></span>"),
+                (None, _) => (),
+            }
+            html.push_str(line);
+            html.push_str("</span>\n");
+        }
+        html.push_str("</code></pre>");
+
+        if self.title.is_some() {
+            html.push_str("</figure>");
+        }
+        html.push('\n');
+        html
+    }
+}
+
+/// Strip the `+`/`-` markers from the lines of a diff, so that the code left over can be
+/// highlighted, returning it along with the kind of each line
+pub fn split_diff(code: &str) -> (String, Vec<DiffLine>) {
+    let mut stripped = String::with_capacity(code.len());
+    let mut lines = Vec::new();
+    for line in code.split_inclusive('\n') {
+        let (kind, rest) = match line.chars().next() {
+            Some('+') => (DiffLine::Added, &line[1..]),
+            Some('-') => (DiffLine::Removed, &line[1..]),
+            // Unified diffs indent unchanged lines by a space to line them up with the others
+            Some(' ') => (DiffLine::Unchanged, &line[1..]),
+            _ => (DiffLine::Unchanged, line),
+        };
+        stripped.push_str(rest);
+        lines.push(kind);
+    }
+    (stripped, lines)
+}
+
+/// Split off the next whitespace separated token, keeping quoted values whole
+fn next_token(info: &str) -> (&str, &str) {
+    let mut in_quotes = false;
+    for (index, char) in info.char_indices() {
+        match char {
+            '"' => in_quotes = !in_quotes,
+            char if char.is_whitespace() && !in_quotes => return info.split_at(index),
+            _ => (),
+        }
+    }
+    (info, "")
+}
+
+/// Parse a line number, e.g. `3`, or range of line numbers, e.g. `5-7`
+fn parse_range(range: &str) -> Option<RangeInclusive<usize>> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    Some(start.trim().parse().ok()?..=end.trim().parse().ok()?)
+}
+
+#[test]
+fn parses_info_strings() {
+    assert_eq!(
+        CodeBlockInfo::parse(r#"rs {3,5-7} title="src/main.rs" linenos"#),
+        CodeBlockInfo {
+            lang: "rs".to_string(),
+            highlighted_lines: vec![3..=3, 5..=7],
+            title: Some("src/main.rs".to_string()),
+            line_numbers: true,
+            diff: false,
+        }
+    );
+    assert_eq!(
+        CodeBlockInfo::parse(r#"title="My code" {2}"#),
+        CodeBlockInfo {
+            highlighted_lines: vec![2..=2],
+            title: Some("My code".to_string()),
+            ..CodeBlockInfo::default()
+        }
+    );
+}
+
+#[test]
+fn parses_diffs() {
+    assert!(CodeBlockInfo::parse("diff-rust").diff);
+    assert!(CodeBlockInfo::parse("rs diff").diff);
+    assert_eq!(CodeBlockInfo::parse("diff-rust").lang, "rust");
+    assert!(!CodeBlockInfo::parse("diff").diff);
+
+    let (code, lines) = split_diff("+let a = 1;\n-let b = 2;\n let c = 3;\n");
+    assert_eq!(code, "let a = 1;\nlet b = 2;\nlet c = 3;\n");
+    assert_eq!(
+        lines,
+        [DiffLine::Added, DiffLine::Removed, DiffLine::Unchanged]
+    );
+}
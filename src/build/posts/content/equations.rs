@@ -0,0 +1,178 @@
+//! Numbering display maths and resolving references to it
+//!
+//! KaTeX numbers each piece of maths on its own, starting from one, and has no way of referring to
+//! maths rendered separately. So numbered environments are rewritten into their starred forms with
+//! an explicit `\tag` on each numbered line, and every `\label` is recorded against its number.
+
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+/// The environments in which every line is numbered unless told otherwise
+const NUMBERED_ENVS: [&str; 4] = ["equation", "align", "alignat", "gather"];
+
+static LABEL_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\label\{([^}]*)\}").unwrap());
+static EQREF_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\eqref\{([^}]*)\}").unwrap());
+static TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\tag\*?\{([^}]*)\}").unwrap());
+static NONUMBER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\(?:nonumber|notag)\b").unwrap());
+static ENV_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\\begin\{(\w+)\}([\s\S]*)\\end\{(\w+)\}$").unwrap());
+
+/// The equation numbers of a post, and the labels given to them
+#[derive(Default)]
+pub struct Equations {
+    next_number: usize,
+    /// The text that each label refers to, e.g. `3` for the third numbered equation
+    labels: HashMap<String, String>,
+}
+
+/// A piece of display maths after it has been numbered
+pub struct Numbered {
+    /// The maths, rewritten so that KaTeX renders the right numbers
+    pub tex: String,
+    /// The labels given to the lines of the maths, which need anchors
+    pub labels: Vec<String>,
+}
+
+impl Equations {
+    /// Number a piece of display maths, which is either a whole AMS environment or the contents of
+    /// `$$`/`\[`, the latter only being numbered if it has a `\label`
+    pub fn number(&mut self, tex: &str) -> Numbered {
+        let mut labels = Vec::new();
+        let tex = match ENV_REGEX.captures(tex) {
+            Some(env) if NUMBERED_ENVS.contains(&&env[1]) && env[1] == env[3] => {
+                let (name, body) = (&env[1], &env[2]);
+                let rows = if name == "equation" {
+                    vec![body]
+                } else {
+                    split_rows(body)
+                };
+                let rows = rows
+                    .into_iter()
+                    .map(|row| self.number_row(row, &mut labels))
+                    .collect::<Vec<_>>()
+                    .join("\\\\");
+                format!("\\begin{{{name}*}}{rows}\\end{{{name}*}}")
+            }
+            Some(_) => tex.to_string(),
+            None if LABEL_REGEX.is_match(tex) => self.number_row(tex, &mut labels),
+            None => tex.to_string(),
+        };
+        Numbered { tex, labels }
+    }
+
+    /// Number a single line, unless it is empty, is marked with `\nonumber`/`\notag`, or already
+    /// has a `\tag`
+    fn number_row(&mut self, row: &str, labels: &mut Vec<String>) -> String {
+        let row_labels = LABEL_REGEX
+            .captures_iter(row)
+            .map(|label| label[1].to_string())
+            .collect::<Vec<_>>();
+        let mut row = LABEL_REGEX.replace_all(row, "").into_owned();
+
+        let text = if let Some(tag) = TAG_REGEX.captures(&row) {
+            Some(tag[1].to_string())
+        } else if NONUMBER_REGEX.is_match(&row) {
+            row = NONUMBER_REGEX.replace_all(&row, "").into_owned();
+            None
+        } else if row.trim().is_empty() {
+            None
+        } else {
+            self.next_number += 1;
+            row.push_str(&format!("\\tag{{{}}}", self.next_number));
+            Some(self.next_number.to_string())
+        };
+
+        if let Some(text) = text {
+            for label in row_labels {
+                self.labels.insert(label.clone(), text.clone());
+                labels.push(label);
+            }
+        }
+        row
+    }
+
+    /// The text of the equation that a label refers to
+    pub fn resolve(&self, label: &str) -> Option<&str> {
+        self.labels.get(label).map(String::as_str)
+    }
+
+    /// Replace every `\eqref` in `text` using `replace`, which is given the label and the text of
+    /// the equation it refers to
+    ///
+    /// Returns the first label that doesn't refer to an equation as an error.
+    pub fn replace_eqrefs(
+        &self,
+        text: &str,
+        mut replace: impl FnMut(&str, &str) -> String,
+    ) -> Result<String, String> {
+        let mut unresolved = None;
+        let replaced =
+            EQREF_REGEX.replace_all(text, |eqref: &Captures| match self.resolve(&eqref[1]) {
+                Some(number) => replace(&eqref[1], number),
+                None => {
+                    unresolved.get_or_insert_with(|| eqref[1].to_string());
+                    String::new()
+                }
+            });
+        match unresolved {
+            Some(label) => Err(label),
+            None => Ok(replaced.into_owned()),
+        }
+    }
+}
+
+/// Split the body of an environment into its lines, i.e. at each `\\` that isn't nested in braces
+/// or in another environment
+fn split_rows(body: &str) -> Vec<&str> {
+    let bytes = body.as_bytes();
+    let mut rows = Vec::new();
+    let (mut depth, mut row_start, mut index) = (0_isize, 0, 0);
+    while index < bytes.len() {
+        match bytes[index] {
+            b'\\' if depth == 0 && bytes.get(index + 1) == Some(&b'\\') => {
+                rows.push(&body[row_start..index]);
+                row_start = index + 2;
+                index += 1;
+            }
+            b'\\' if body[index..].starts_with("\\begin{") => depth += 1,
+            b'\\' if body[index..].starts_with("\\end{") => depth -= 1,
+            b'\\' => index += 1,
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            _ => (),
+        }
+        index += 1;
+    }
+    rows.push(&body[row_start..]);
+    rows
+}
+
+#[test]
+fn numbers_lines_and_labels() {
+    let mut equations = Equations::default();
+    let align = equations.number(
+        r"\begin{align}a &= b \label{eq:ab} \\ c &= \begin{cases} d \\ e \end{cases} \nonumber \\ f &= g\end{align}",
+    );
+    assert_eq!(
+        align.tex,
+        r"\begin{align*}a &= b  \tag{1}\\ c &= \begin{cases} d \\ e \end{cases}  \\ f &= g\tag{2}\end{align*}"
+    );
+    assert_eq!(align.labels, ["eq:ab"]);
+
+    let unlabelled = equations.number(r"x^2");
+    assert_eq!(unlabelled.tex, r"x^2");
+    let labelled = equations.number(r"y^2 \label{eq:y}");
+    assert_eq!(labelled.tex, r"y^2 \tag{3}");
+
+    assert_eq!(
+        equations.replace_eqrefs(r"\eqref{eq:ab} and \eqref{eq:y}", |_, number| number
+            .to_string()),
+        Ok("1 and 3".to_string())
+    );
+    assert_eq!(
+        equations.replace_eqrefs(r"\eqref{eq:nope}", |_, number| number.to_string()),
+        Err("eq:nope".to_string())
+    );
+}
@@ -0,0 +1,427 @@
+//! Generating the stylesheet for the highlight classes in [`HTML_ATTRS`] from helix themes
+//!
+//! A helix theme maps scopes such as `keyword.control` to styles, where a style is either just a
+//! foreground colour or a table of `fg`, `bg` and `modifiers`, and colours can be given by name from
+//! the theme's `[palette]`. Highlight names that a theme doesn't mention fall back to their parent
+//! scopes, e.g. `keyword.function` to `keyword`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use snafu::ResultExt;
+use toml::Value;
+
+use crate::{
+    build::{BuildError, ParseThemeSnafu},
+    HighlightTheme,
+};
+
+use super::highlight::{HIGHLIGHT_NAMES, HTML_ATTRS};
+
+/// The scopes that helix uses for the highlight names which don't match its own
+const HELIX_SCOPES: &[(&str, &str)] = &[
+    ("annotation", "attribute"),
+    ("boolean", "constant.builtin.boolean"),
+    ("character", "constant.character"),
+    ("conditional", "keyword.control.conditional"),
+    ("exception", "keyword.control.exception"),
+    ("field", "variable.other.member"),
+    ("float", "constant.numeric.float"),
+    ("include", "keyword.control.import"),
+    ("method", "function.method"),
+    ("number", "constant.numeric"),
+    ("parameter", "variable.parameter"),
+    ("property", "variable.other.member"),
+    ("repeat", "keyword.control.repeat"),
+    ("storageClass", "keyword.storage.modifier"),
+    ("string.regex", "string.regexp"),
+    ("stringEscape", "constant.character.escape"),
+    ("structure", "type"),
+    ("tag.attribute", "attribute"),
+    ("text", "ui.text"),
+    ("text.danger", "error"),
+    ("text.diff.add", "diff.plus"),
+    ("text.diff.delete", "diff.minus"),
+    ("text.emphasis", "markup.italic"),
+    ("text.literal", "markup.raw"),
+    ("text.note", "info"),
+    ("text.strike", "markup.strikethrough"),
+    ("text.strong", "markup.bold"),
+    ("text.title", "markup.heading"),
+    ("text.underline", "markup.underline"),
+    ("text.uri", "markup.link.url"),
+    ("text.warning", "warning"),
+    ("textReference", "markup.link.text"),
+    ("type.qualifier", "keyword.storage.modifier"),
+];
+
+/// Helix's named colours, which aren't all valid CSS colours
+const NAMED_COLOURS: &[(&str, &str)] = &[
+    ("gray", "#808080"),
+    ("light-red", "#ff5f5f"),
+    ("light-green", "#5fff5f"),
+    ("light-yellow", "#ffff5f"),
+    ("light-blue", "#5f87ff"),
+    ("light-magenta", "#ff5fff"),
+    ("light-cyan", "#5fffff"),
+    ("light-gray", "#d0d0d0"),
+];
+
+/// A helix theme, with any themes it inherits from merged into it
+pub struct Theme {
+    styles: toml::value::Table,
+    palette: toml::value::Table,
+    /// The files that the theme was read from, its own followed by those it inherits from
+    files: Vec<PathBuf>,
+}
+
+impl Theme {
+    /// Read a helix theme, along with the themes it `inherits` from, which are looked for in the
+    /// same directory
+    pub fn read(path: &Path) -> Result<Self, BuildError> {
+        Self::read_inheriting(path, &mut Vec::new())
+    }
+
+    /// Read a helix theme that is inherited from by the themes in `children`, which it mustn't
+    /// inherit from in turn
+    fn read_inheriting(path: &Path, children: &mut Vec<PathBuf>) -> Result<Self, BuildError> {
+        if children.iter().any(|child| child == path) {
+            return Err(BuildError::ThemeCycle {
+                path: path.to_path_buf(),
+            });
+        }
+        let source = fs::read_to_string(path).map_err(|err| (err, path.to_path_buf()))?;
+        let mut styles: toml::value::Table =
+            toml::from_str(&source).context(ParseThemeSnafu { path })?;
+        let mut palette = match styles.remove("palette") {
+            Some(Value::Table(palette)) => palette,
+            _ => toml::value::Table::new(),
+        };
+
+        let mut files = vec![path.to_path_buf()];
+        if let Some(Value::String(parent)) = styles.remove("inherits") {
+            let parent_path = path.with_file_name(format!("{parent}.toml"));
+            // Helix's own themes aren't available, so there's nothing to inherit from them
+            if parent_path.exists() {
+                children.push(path.to_path_buf());
+                let parent = Theme::read_inheriting(&parent_path, children)?;
+                for (key, value) in parent.styles {
+                    styles.entry(key).or_insert(value);
+                }
+                for (key, value) in parent.palette {
+                    palette.entry(key).or_insert(value);
+                }
+                files.extend(parent.files);
+            }
+        }
+
+        Ok(Self {
+            styles,
+            palette,
+            files,
+        })
+    }
+
+    /// The CSS declarations for a highlight name, e.g. `color: #ff0000; font-weight: bold`
+    fn declarations(&self, highlight_name: &str) -> String {
+        let helix_scope = HELIX_SCOPES
+            .iter()
+            .find(|(name, _)| *name == highlight_name)
+            .map_or(highlight_name, |(_, scope)| scope);
+        let style = [helix_scope, highlight_name]
+            .into_iter()
+            .flat_map(parent_scopes)
+            .find_map(|scope| self.styles.get(scope));
+
+        let mut declarations = Vec::new();
+        match style {
+            Some(Value::String(fg)) => declarations.push(format!("color: {}", self.colour(fg))),
+            Some(Value::Table(style)) => {
+                if let Some(Value::String(fg)) = style.get("fg") {
+                    declarations.push(format!("color: {}", self.colour(fg)));
+                }
+                if let Some(Value::String(bg)) = style.get("bg") {
+                    declarations.push(format!("background-color: {}", self.colour(bg)));
+                }
+                let modifiers = match style.get("modifiers") {
+                    Some(Value::Array(modifiers)) => modifiers.as_slice(),
+                    _ => &[],
+                };
+                for modifier in modifiers.iter().filter_map(Value::as_str) {
+                    declarations.push(
+                        match modifier {
+                            "bold" => "font-weight: bold",
+                            "dim" => "opacity: 0.7",
+                            "italic" => "font-style: italic",
+                            "underlined" => "text-decoration: underline",
+                            "crossed_out" => "text-decoration: line-through",
+                            _ => continue,
+                        }
+                        .to_string(),
+                    );
+                }
+                if style.contains_key("underline") {
+                    declarations.push("text-decoration: underline".to_string());
+                }
+            }
+            _ => (),
+        }
+        // Every class gets a colour, so that no colours from the theme's own stylesheets leak in
+        if !declarations.iter().any(|decl| decl.starts_with("color")) {
+            declarations.insert(0, "color: inherit".to_string());
+        }
+        declarations.join("; ")
+    }
+
+    /// Resolve a colour from the palette or helix's named colours
+    fn colour(&self, colour: &str) -> String {
+        if let Some(Value::String(colour)) = self.palette.get(colour) {
+            return colour.clone();
+        }
+        NAMED_COLOURS
+            .iter()
+            .find(|(name, _)| *name == colour)
+            .map_or(colour, |(_, css)| css)
+            .to_string()
+    }
+
+    /// The colour of part of helix's UI, if the theme sets it
+    fn ui_colour(&self, scope: &str, key: &str) -> Option<String> {
+        match self.styles.get(scope)? {
+            Value::String(fg) if key == "fg" => Some(self.colour(fg)),
+            Value::Table(style) => Some(self.colour(style.get(key)?.as_str()?)),
+            _ => None,
+        }
+    }
+
+    /// Write the rules of this theme to `css`, each selector being prefixed with `prefixes`
+    fn write_rules(&self, css: &mut String, prefixes: &[&str]) {
+        let selector = |element: &str| {
+            prefixes
+                .iter()
+                .map(|prefix| format!("{prefix} {element}"))
+                .collect::<Vec<_>>()
+                .join(",\n")
+        };
+
+        if let Some(background) = self.ui_colour("ui.background", "bg") {
+            css.push_str(&format!(
+                "{} {{ background-color: {background} }}\n",
+                selector("pre")
+            ));
+        }
+        if let Some(foreground) = self.ui_colour("ui.text", "fg") {
+            css.push_str(&format!("{} {{ color: {foreground} }}\n", selector("code")));
+        }
+        for (name, attr) in HIGHLIGHT_NAMES.iter().zip(HTML_ATTRS) {
+            let class = attr
+                .trim_start_matches("class=\"")
+                .trim_end_matches('"')
+                .replace(' ', ".");
+            css.push_str(&format!(
+                "{} {{ {} }}\n",
+                selector(&format!(".{class}")),
+                self.declarations(name)
+            ));
+        }
+    }
+}
+
+/// A theme resolved into inline styles, for highlighting code where the site's stylesheets aren't
+/// loaded, e.g. in feeds and emails
+pub struct InlineTheme {
+    /// The `style` attribute of each highlight, indexed like [`HTML_ATTRS`]
+    pub attrs: Vec<String>,
+    /// The inline style of the `<pre>` of each code block, if the theme has UI colours
+    pub pre_style: Option<String>,
+    /// The inline style of the lines picked out by a code block's info string
+    pub highlighted_line_style: String,
+    /// The inline style of the line numbers of a code block
+    pub line_number_style: String,
+    /// The inline style of the added and removed lines of a diff, indexed by
+    /// [`DiffLine`](super::code_block::DiffLine)
+    pub diff_line_styles: [String; 2],
+    /// The inline style of the markers of the lines of a diff, indexed by
+    /// [`DiffLine`](super::code_block::DiffLine)
+    pub diff_marker_styles: [String; 3],
+    /// The inline style of the prompts in a terminal session
+    pub prompt_style: String,
+    /// The CSS colour of each of the 16 standard ANSI colours, where 8-15 are the bright versions
+    /// of 0-7
+    pub ansi_colours: Vec<String>,
+}
+
+/// The helix colours that terminals use for the 16 standard ANSI colours
+const ANSI_COLOURS: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "light-gray",
+    "gray",
+    "light-red",
+    "light-green",
+    "light-yellow",
+    "light-blue",
+    "light-magenta",
+    "light-cyan",
+    "white",
+];
+
+impl Theme {
+    fn inline(&self) -> InlineTheme {
+        let attrs = HIGHLIGHT_NAMES
+            .iter()
+            .map(|name| format!("style=\"{}\"", self.declarations(name)))
+            .collect();
+        let pre_style = [
+            ("background-color", self.ui_colour("ui.background", "bg")),
+            ("color", self.ui_colour("ui.text", "fg")),
+        ]
+        .into_iter()
+        .filter_map(|(property, colour)| Some(format!("{property}: {}", colour?)))
+        .collect::<Vec<_>>()
+        .join("; ");
+
+        // These mirror the rules for the same classes in the default theme's stylesheet
+        let highlighted_line = ["ui.cursorline.primary", "ui.cursorline", "ui.selection"]
+            .into_iter()
+            .find_map(|scope| self.ui_colour(scope, "bg"));
+        let highlighted_line_style = match highlighted_line {
+            Some(colour) => {
+                format!("display: inline-block; min-width: 100%; background-color: {colour}")
+            }
+            None => "font-weight: bold".to_string(),
+        };
+        let line_number = self
+            .ui_colour("ui.linenr", "fg")
+            .unwrap_or_else(|| "inherit".to_string());
+        let line_number_style = format!(
+            "display: inline-block; min-width: 2.5ch; margin-right: 1.5ch; text-align: right; \
+             user-select: none; color: {line_number}"
+        );
+        // Themes tend to only colour the text of diffs, so the lines fall back to pale backgrounds,
+        // as the inline theme is the light one
+        let diff_line_styles =
+            [("diff.plus", "#e6ffec"), ("diff.minus", "#ffebe9")].map(|(scope, fallback)| {
+                let colour = self
+                    .ui_colour(scope, "bg")
+                    .unwrap_or_else(|| fallback.to_string());
+                format!("display: inline-block; min-width: 100%; background-color: {colour}")
+            });
+        let diff_marker_styles = ["text.diff.add", "text.diff.delete", "text"].map(|name| {
+            format!(
+                "margin-right: 1ch; user-select: none; {}",
+                self.declarations(name)
+            )
+        });
+        let prompt_style = format!("user-select: none; {}", self.declarations("comment"));
+        let ansi_colours = ANSI_COLOURS
+            .into_iter()
+            .map(|colour| self.colour(colour))
+            .collect();
+
+        InlineTheme {
+            attrs,
+            pre_style: (!pre_style.is_empty()).then_some(pre_style),
+            highlighted_line_style,
+            line_number_style,
+            diff_line_styles,
+            diff_marker_styles,
+            prompt_style,
+            ansi_colours,
+        }
+    }
+}
+
+/// A scope followed by its parents, e.g. `a.b.c`, `a.b` and `a`
+fn parent_scopes(scope: &str) -> impl Iterator<Item = &str> {
+    let mut scope = Some(scope);
+    std::iter::from_fn(move || {
+        let current = scope?;
+        scope = current.rsplit_once('.').map(|(parent, _)| parent);
+        Some(current)
+    })
+}
+
+/// Generate the stylesheet for the highlight classes, using `dark` for the dark and default themes,
+/// and `light` for the light theme, as toggled by the `data-theme` attribute of the `<body>`
+pub fn stylesheet(light: &Theme, dark: &Theme) -> String {
+    let mut css = String::from("/* Generated by Hyde from helix themes, do not edit */\n\n");
+    dark.write_rules(
+        &mut css,
+        &[
+            r#"body[data-theme="default"]"#,
+            r#"body[data-theme="dark"]"#,
+        ],
+    );
+    css.push('\n');
+    light.write_rules(&mut css, &[r#"body[data-theme="light"]"#]);
+    css
+}
+
+/// Read the themes for the `[highlight] theme` option, which are relative to the project directory
+/// `dir`, and generate their stylesheet
+pub fn theme_stylesheet(dir: &Path, theme: &HighlightTheme) -> Result<String, BuildError> {
+    let light = Theme::read(&dir.join(theme.light()))?;
+    let dark = Theme::read(&dir.join(theme.dark()))?;
+    Ok(stylesheet(&light, &dark))
+}
+
+/// Read the light theme for the `[highlight] theme` option as inline styles, as feed readers and
+/// email clients tend to be light
+pub fn inline_theme(dir: &Path, theme: &HighlightTheme) -> Result<InlineTheme, BuildError> {
+    Ok(Theme::read(&dir.join(theme.light()))?.inline())
+}
+
+/// The files of the themes for the `[highlight] theme` option, including those they inherit from,
+/// which posts depend on
+pub fn theme_files(dir: &Path, theme: &HighlightTheme) -> Result<Vec<PathBuf>, BuildError> {
+    let mut files = Theme::read(&dir.join(theme.light()))?.files;
+    files.extend(Theme::read(&dir.join(theme.dark()))?.files);
+    Ok(files)
+}
+
+#[test]
+fn rejects_inheritance_cycles() {
+    let dir = std::env::temp_dir().join(format!("hyde-theme-cycle-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.toml"), "inherits = \"b\"").unwrap();
+    fs::write(dir.join("b.toml"), "inherits = \"a\"").unwrap();
+    fs::write(dir.join("c.toml"), "inherits = \"c\"").unwrap();
+    let cycle = |name| {
+        matches!(
+            Theme::read(&dir.join(name)),
+            Err(BuildError::ThemeCycle { .. })
+        )
+    };
+    assert!(cycle("a.toml"));
+    assert!(cycle("c.toml"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn falls_back_to_parent_scopes() {
+    let theme = Theme {
+        styles: toml::from_str(
+            r#"
+            "keyword" = "red"
+            "keyword.control.conditional" = { fg = "blue", modifiers = ["bold"] }
+            "#,
+        )
+        .unwrap(),
+        palette: toml::from_str(r##"blue = "#0000ff""##).unwrap(),
+        files: Vec::new(),
+    };
+    assert_eq!(theme.declarations("keyword.function"), "color: red");
+    assert_eq!(
+        theme.declarations("conditional"),
+        "color: #0000ff; font-weight: bold"
+    );
+    assert_eq!(theme.declarations("comment"), "color: inherit");
+}
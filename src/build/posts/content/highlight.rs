@@ -1,13 +1,31 @@
-use std::str::FromStr;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    env::{self, consts::DLL_EXTENSION},
+    fs, io,
+    path::{Path, PathBuf},
+};
 
+use libloading::Library;
 use once_cell::sync::Lazy;
-use pulldown_cmark::CowStr;
+use snafu::ResultExt;
+use tree_sitter::{Language, LANGUAGE_VERSION, MIN_COMPATIBLE_LANGUAGE_VERSION};
 use tree_sitter_highlight::{HighlightConfiguration, Highlighter, HtmlRenderer};
 
+use crate::{
+    build::{read_dir, warn, BuildError, GrammarQuerySnafu, LoadGrammarSnafu},
+    HighlightConfig,
+};
+
+use super::{
+    cache::{Cache, Key, KeyHasher},
+    helix::InlineTheme,
+};
+
 /* WARNING: It is absolutely imperative that `HIGHLIGHT_NAMES` and `HTML_ATTRS` line up exactly */
 
 // The list of recognised treesitter highlight names, as stolen from some helix theme
-const HIGHLIGHT_NAMES: &[&str] = &[
+pub(super) const HIGHLIGHT_NAMES: &[&str] = &[
     "error",
     "punctuation.bracket",
     "punctuation.special",
@@ -74,7 +92,7 @@ const HIGHLIGHT_NAMES: &[&str] = &[
 ];
 
 // The highlight names turned into HTML class attributes
-const HTML_ATTRS: &[&str] = &[
+pub(super) const HTML_ATTRS: &[&str] = &[
     r#"class="error""#,
     r#"class="punctuation-bracket""#,
     r#"class="punctuation-special""#,
@@ -140,132 +158,647 @@ const HTML_ATTRS: &[&str] = &[
     r#"class="text-diff-delete""#,
 ];
 
+/// Build the highlight configuration for a grammar that is compiled into Hyde
+// Unused if every grammar's feature is disabled
+#[allow(dead_code)]
+fn bundled(
+    language: Language,
+    highlights: &str,
+    injections: &str,
+    locals: &str,
+) -> HighlightConfiguration {
+    let mut config = HighlightConfiguration::new(language, highlights, injections, locals).unwrap();
+    config.configure(HIGHLIGHT_NAMES);
+    config
+}
+
+#[cfg(feature = "bash")]
+static BASH_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_bash::language(),
+        tree_sitter_bash::HIGHLIGHT_QUERY,
+        "",
+        "",
+    )
+});
+#[cfg(feature = "c")]
 static C_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
-    let mut config = HighlightConfiguration::new(
+    bundled(
         tree_sitter_c::language(),
         tree_sitter_c::HIGHLIGHT_QUERY,
         "",
         "",
     )
-    .unwrap();
-    config.configure(HIGHLIGHT_NAMES);
-    config
 });
+#[cfg(feature = "cpp")]
 static CPP_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
-    let mut config = HighlightConfiguration::new(
+    bundled(
         tree_sitter_cpp::language(),
         tree_sitter_cpp::HIGHLIGHT_QUERY,
+        include_str!("queries/cpp/injections.scm"),
+        "",
+    )
+});
+#[cfg(feature = "css")]
+static CSS_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_css::language(),
+        tree_sitter_css::HIGHLIGHTS_QUERY,
+        "",
+        "",
+    )
+});
+#[cfg(feature = "go")]
+static GO_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_go::language(),
+        tree_sitter_go::HIGHLIGHT_QUERY,
         "",
         "",
     )
-    .unwrap();
-    config.configure(HIGHLIGHT_NAMES);
-    config
 });
+#[cfg(feature = "haskell")]
 static HASKELL_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
-    let mut config = HighlightConfiguration::new(
+    bundled(
         npezza93_tree_sitter_haskell::language(),
         npezza93_tree_sitter_haskell::HIGHLIGHTS_QUERY,
         "",
+        npezza93_tree_sitter_haskell::LOCALS_QUERY,
+    )
+});
+#[cfg(feature = "html")]
+static HTML_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_html::language(),
+        tree_sitter_html::HIGHLIGHTS_QUERY,
+        tree_sitter_html::INJECTIONS_QUERY,
         "",
     )
-    .unwrap();
-    config.configure(HIGHLIGHT_NAMES);
-    config
 });
+#[cfg(feature = "java")]
+static JAVA_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_java::language(),
+        tree_sitter_java::HIGHLIGHT_QUERY,
+        "",
+        "",
+    )
+});
+#[cfg(feature = "javascript")]
+static JAVASCRIPT_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_javascript::language(),
+        &format!(
+            "{}\n{}",
+            tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+            tree_sitter_javascript::HIGHLIGHT_QUERY
+        ),
+        tree_sitter_javascript::INJECTION_QUERY,
+        tree_sitter_javascript::LOCALS_QUERY,
+    )
+});
+#[cfg(feature = "json")]
+static JSON_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_json::language(),
+        tree_sitter_json::HIGHLIGHT_QUERY,
+        "",
+        "",
+    )
+});
+#[cfg(feature = "markdown")]
+static MARKDOWN_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_md::language(),
+        tree_sitter_md::HIGHLIGHT_QUERY_BLOCK,
+        tree_sitter_md::INJECTION_QUERY_BLOCK,
+        "",
+    )
+});
+// Markdown is parsed in two passes, with the inline grammar injected into each block
+#[cfg(feature = "markdown")]
+static MARKDOWN_INLINE_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_md::inline_language(),
+        include_str!("queries/markdown_inline/highlights.scm"),
+        tree_sitter_md::INJECTION_QUERY_INLINE,
+        "",
+    )
+});
+#[cfg(feature = "ocaml")]
 static OCAML_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
-    let mut config = HighlightConfiguration::new(
+    bundled(
         tree_sitter_ocaml::language_ocaml(),
         tree_sitter_ocaml::HIGHLIGHTS_QUERY,
         "",
-        "",
+        tree_sitter_ocaml::LOCALS_QUERY,
     )
-    .unwrap();
-    config.configure(HIGHLIGHT_NAMES);
-    config
 });
+#[cfg(feature = "python")]
 static PYTHON_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
-    let mut config = HighlightConfiguration::new(
+    bundled(
         tree_sitter_python::language(),
         tree_sitter_python::HIGHLIGHT_QUERY,
         "",
         "",
     )
-    .unwrap();
-    config.configure(HIGHLIGHT_NAMES);
-    config
 });
+#[cfg(feature = "rust")]
 static RUST_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
-    let mut config = HighlightConfiguration::new(
+    bundled(
         tree_sitter_rust::language(),
         tree_sitter_rust::HIGHLIGHT_QUERY,
+        tree_sitter_rust::INJECTIONS_QUERY,
+        "",
+    )
+});
+#[cfg(feature = "toml")]
+static TOML_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_toml::language(),
+        tree_sitter_toml::HIGHLIGHT_QUERY,
+        "",
+        "",
+    )
+});
+// The TypeScript queries only cover what TypeScript adds to JavaScript, so come before them
+#[cfg(feature = "typescript")]
+static TYPESCRIPT_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_typescript::language_typescript(),
+        &format!(
+            "{}\n{}",
+            tree_sitter_typescript::HIGHLIGHT_QUERY,
+            tree_sitter_javascript::HIGHLIGHT_QUERY
+        ),
+        tree_sitter_javascript::INJECTION_QUERY,
+        &format!(
+            "{}\n{}",
+            tree_sitter_typescript::LOCALS_QUERY,
+            tree_sitter_javascript::LOCALS_QUERY
+        ),
+    )
+});
+#[cfg(feature = "typescript")]
+static TSX_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_typescript::language_tsx(),
+        &format!(
+            "{}\n{}\n{}",
+            tree_sitter_typescript::HIGHLIGHT_QUERY,
+            tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+            tree_sitter_javascript::HIGHLIGHT_QUERY
+        ),
+        tree_sitter_javascript::INJECTION_QUERY,
+        &format!(
+            "{}\n{}",
+            tree_sitter_typescript::LOCALS_QUERY,
+            tree_sitter_javascript::LOCALS_QUERY
+        ),
+    )
+});
+#[cfg(feature = "yaml")]
+static YAML_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    bundled(
+        tree_sitter_yaml::language(),
+        include_str!("queries/yaml/highlights.scm"),
         "",
         "",
     )
-    .unwrap();
-    config.configure(HIGHLIGHT_NAMES);
-    config
 });
 
-/// Any language that Hyde recognises inside a fenced code block
-enum Language {
-    C,
-    Cpp,
-    Haskell,
-    OCaml,
-    Python,
-    Rust,
+/// The grammars compiled into Hyde, by name, each of which can be left out with its cargo feature
+static BUNDLED: &[(&str, &Lazy<HighlightConfiguration>)] = &[
+    #[cfg(feature = "bash")]
+    ("bash", &BASH_CONFIG),
+    #[cfg(feature = "c")]
+    ("c", &C_CONFIG),
+    #[cfg(feature = "cpp")]
+    ("cpp", &CPP_CONFIG),
+    #[cfg(feature = "css")]
+    ("css", &CSS_CONFIG),
+    #[cfg(feature = "go")]
+    ("go", &GO_CONFIG),
+    #[cfg(feature = "haskell")]
+    ("haskell", &HASKELL_CONFIG),
+    #[cfg(feature = "html")]
+    ("html", &HTML_CONFIG),
+    #[cfg(feature = "java")]
+    ("java", &JAVA_CONFIG),
+    #[cfg(feature = "javascript")]
+    ("javascript", &JAVASCRIPT_CONFIG),
+    #[cfg(feature = "json")]
+    ("json", &JSON_CONFIG),
+    #[cfg(feature = "markdown")]
+    ("markdown", &MARKDOWN_CONFIG),
+    #[cfg(feature = "markdown")]
+    ("markdown_inline", &MARKDOWN_INLINE_CONFIG),
+    #[cfg(feature = "ocaml")]
+    ("ocaml", &OCAML_CONFIG),
+    #[cfg(feature = "python")]
+    ("python", &PYTHON_CONFIG),
+    #[cfg(feature = "rust")]
+    ("rust", &RUST_CONFIG),
+    #[cfg(feature = "toml")]
+    ("toml", &TOML_CONFIG),
+    #[cfg(feature = "typescript")]
+    ("typescript", &TYPESCRIPT_CONFIG),
+    #[cfg(feature = "typescript")]
+    ("tsx", &TSX_CONFIG),
+    #[cfg(feature = "yaml")]
+    ("yaml", &YAML_CONFIG),
+];
+
+/// Other names that grammars go by in fenced code blocks, on top of the configured
+/// `[highlight.aliases]`
+// Just going to assume there's no standard for this kind of thing and wing it
+const ALIASES: &[(&str, &str)] = &[
+    ("c++", "cpp"),
+    ("cc", "cpp"),
+    ("cjs", "javascript"),
+    ("golang", "go"),
+    ("hs", "haskell"),
+    ("htm", "html"),
+    ("js", "javascript"),
+    ("jsx", "javascript"),
+    ("md", "markdown"),
+    ("mjs", "javascript"),
+    ("ml", "ocaml"),
+    ("py", "python"),
+    ("rs", "rust"),
+    ("sh", "bash"),
+    ("shell", "bash"),
+    ("ts", "typescript"),
+    ("yml", "yaml"),
+    ("zsh", "bash"),
+];
+
+enum Grammar {
+    /// Compiled into Hyde, and only configured the first time it is used
+    Bundled(&'static Lazy<HighlightConfiguration>),
+    /// Loaded from a shared object at runtime
+    Loaded(Box<HighlightConfiguration>),
+}
+
+/// Every tree-sitter grammar that Hyde can highlight fenced code blocks with, both those compiled
+/// into Hyde and those loaded at runtime from the grammar directory
+///
+/// # Grammar directory
+///
+/// Each grammar in the directory consists of a shared object named after the language (e.g.
+/// `go.so` on Linux), which exports the usual `tree_sitter_<language>` function, and a directory of
+/// queries of the same name, which must contain `highlights.scm` and may contain `injections.scm`
+/// and `locals.scm`:
+///
+/// ```text
+/// grammars/
+/// ├── go.so
+/// └── go/
+///     ├── highlights.scm
+///     ├── injections.scm
+///     └── locals.scm
+/// ```
+///
+/// As with helix, a query starting with `; inherits: <language>,...` includes the queries of the
+/// same name from those languages. A loaded grammar takes precedence over a bundled one of the
+/// same name.
+pub struct Registry {
+    grammars: HashMap<String, Grammar>,
+    /// The configured `[highlight.aliases]`
+    aliases: BTreeMap<String, String>,
+    /// The configured languages that are never highlighted
+    disabled: Vec<String>,
+    /// A hash of every grammar and alias, for keying the cache
+    fingerprint: Key,
+    cache: Option<Cache>,
+    /// The highlighter and renderer are reused, as they hold on to buffers that would otherwise be
+    /// reallocated for every piece of code
+    ///
+    /// The highlighter holds on to the last grammar it used, so it mustn't outlive the libraries.
+    highlighter: RefCell<(Highlighter, HtmlRenderer)>,
+    /// The libraries that the loaded grammars live in, which must outlive them (hence the field
+    /// order)
+    _libraries: Vec<Library>,
 }
 
-impl FromStr for Language {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // Just going to assume there's no standard for this kind of thing and wing it
-        match s {
-            "c" => Ok(Self::C),
-            "cpp" | "c++" => Ok(Self::Cpp),
-            "haskell" | "hs" => Ok(Self::Haskell),
-            "ocaml" | "ml" => Ok(Self::OCaml),
-            "python" | "py" => Ok(Self::Python),
-            "rs" | "rust" => Ok(Self::Rust),
-            _ => Err(()),
+impl Registry {
+    /// Gather the bundled grammars and load those in the configured grammar directory, which
+    /// defaults to `$XDG_CONFIG_HOME/hyde/grammars` (or `~/.config/hyde/grammars`)
+    pub fn new(config: &HighlightConfig) -> Result<Self, BuildError> {
+        let mut grammars = BUNDLED
+            .iter()
+            .map(|&(name, config)| (name.to_string(), Grammar::Bundled(config)))
+            .collect::<HashMap<_, _>>();
+        let mut libraries = Vec::new();
+        // The bundled grammars only change along with Hyde
+        let version = KeyHasher::default()
+            .input(env!("CARGO_PKG_VERSION"))
+            .finish();
+        let mut versions = BTreeMap::from([(String::new(), version)]);
+
+        let grammars_dir = config.grammars.clone().or_else(default_grammars_dir);
+        if let Some(grammars_dir) = grammars_dir.filter(|dir| dir.is_dir()) {
+            for entry in read_dir(&grammars_dir, &[])? {
+                let path = entry.path();
+                if path.extension() != Some(DLL_EXTENSION.as_ref()) {
+                    continue;
+                }
+                let (name, config, library, version) = load_grammar(&grammars_dir, &path)?;
+                versions.insert(name.clone(), version);
+                grammars.insert(name, Grammar::Loaded(Box::new(config)));
+                libraries.push(library);
+            }
         }
+
+        let mut fingerprint = KeyHasher::default();
+        for (name, version) in versions {
+            fingerprint.input(name).input(version);
+        }
+        for (alias, name) in &config.aliases {
+            fingerprint.input(alias).input(name);
+        }
+
+        Ok(Self {
+            grammars,
+            aliases: config.aliases.clone(),
+            disabled: config.disabled.clone(),
+            fingerprint: fingerprint.finish(),
+            cache: None,
+            highlighter: RefCell::new((Highlighter::new(), HtmlRenderer::new())),
+            _libraries: libraries,
+        })
+    }
+
+    /// Highlight code with a grammar's configuration, without going through the cache
+    fn render(
+        &self,
+        config: &HighlightConfiguration,
+        mode: HighlightMode,
+        code: &str,
+    ) -> Result<String, tree_sitter_highlight::Error> {
+        let (highlighter, renderer) = &mut *self.highlighter.borrow_mut();
+        let highlights =
+            highlighter.highlight(config, code.as_bytes(), None, |injected| self.get(injected))?;
+        renderer.reset();
+        renderer.render(highlights, code.as_bytes(), &|highlight| match mode {
+            HighlightMode::Classes => HTML_ATTRS[highlight.0].as_bytes(),
+            HighlightMode::InlineStyles(theme) => theme.attrs[highlight.0].as_bytes(),
+        })?;
+        Ok(String::from_utf8_lossy(&renderer.html).into_owned())
+    }
+
+    /// Whether highlighting a language is disabled by `[highlight] disabled`, under the name it is
+    /// given by or any name that it is an alias of
+    pub fn is_disabled(&self, name: &str) -> bool {
+        self.names(name)
+            .iter()
+            .any(|name| self.disabled.iter().any(|disabled| disabled == name))
+    }
+
+    /// A language's name followed by what it is an alias of in the configured `aliases`, then what
+    /// that is an alias of in the built-in [`ALIASES`], the last being the name of its grammar
+    fn names<'a>(&'a self, name: &'a str) -> [&'a str; 3] {
+        let configured = self.aliases.get(name).map_or(name, String::as_str);
+        let built_in = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == configured)
+            .map_or(configured, |(_, name)| name);
+        [name, configured, built_in]
+    }
+
+    /// Cache highlighted code in `cache` from now on
+    pub fn with_cache(self, cache: Option<Cache>) -> Self {
+        Self { cache, ..self }
+    }
+
+    /// The cache that highlighted code is kept in, if any
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// Find the grammar for a language by its name or one of its aliases, unless highlighting it is
+    /// disabled
+    pub fn get(&self, name: &str) -> Option<&HighlightConfiguration> {
+        if self.is_disabled(name) {
+            return None;
+        }
+        let [.., name] = self.names(name);
+        self.grammars.get(name).map(|grammar| match grammar {
+            Grammar::Bundled(config) => &***config,
+            Grammar::Loaded(config) => &**config,
+        })
     }
 }
 
-impl Language {
-    fn get_config(&self) -> &HighlightConfiguration {
+fn default_grammars_dir() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("hyde").join("grammars"))
+}
+
+/// Load a grammar from a shared object in the grammar directory, along with its queries, and a hash
+/// of them both as its version
+fn load_grammar(
+    grammars_dir: &Path,
+    path: &Path,
+) -> Result<(String, HighlightConfiguration, Library, Key), BuildError> {
+    let name = path
+        .file_stem()
+        .expect("missing filename")
+        .to_string_lossy()
+        .into_owned();
+    let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+
+    // SAFETY: There is no way of checking that a shared object in the grammar directory is
+    // actually a tree-sitter grammar, so this is as good as it gets
+    let (library, language) = unsafe {
+        let library = Library::new(path).context(LoadGrammarSnafu { path })?;
+        let language = library
+            .get::<unsafe extern "C" fn() -> Language>(symbol.as_bytes())
+            .context(LoadGrammarSnafu { path })?();
+        (library, language)
+    };
+    if !(MIN_COMPATIBLE_LANGUAGE_VERSION..=LANGUAGE_VERSION).contains(&language.version()) {
+        return Err(BuildError::GrammarVersion {
+            version: language.version(),
+            path: path.to_path_buf(),
+        });
+    }
+
+    let queries_dir = grammars_dir.join(&name);
+    let [highlights, injections, locals] =
+        ["highlights", "injections", "locals"].map(|kind| read_query(grammars_dir, &name, kind));
+    let highlights = highlights.map_err(|err| (err, queries_dir.join("highlights.scm")))?;
+    let (injections, locals) = (injections.unwrap_or_default(), locals.unwrap_or_default());
+    let mut config = HighlightConfiguration::new(language, &highlights, &injections, &locals)
+        .context(GrammarQuerySnafu { path: queries_dir })?;
+    config.configure(HIGHLIGHT_NAMES);
+
+    let object = fs::read(path).map_err(|err| (err, path.to_path_buf()))?;
+    let version = KeyHasher::default()
+        .input(object)
+        .input(highlights)
+        .input(injections)
+        .input(locals)
+        .finish();
+    Ok((name, config, library, version))
+}
+
+/// Read one of a language's queries from the grammar directory, including those it inherits
+fn read_query(grammars_dir: &Path, language: &str, kind: &str) -> io::Result<String> {
+    read_inherited_query(grammars_dir, language, kind, &mut Vec::new())
+}
+
+/// Read one of a language's queries that is inherited by the queries of `children`, skipping any
+/// that it would inherit from them in turn
+fn read_inherited_query(
+    grammars_dir: &Path,
+    language: &str,
+    kind: &str,
+    children: &mut Vec<String>,
+) -> io::Result<String> {
+    let query = fs::read_to_string(grammars_dir.join(language).join(format!("{kind}.scm")))?;
+    let inherits = query
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("; inherits:"));
+    let Some(inherits) = inherits else {
+        return Ok(query);
+    };
+
+    children.push(language.to_string());
+    let mut combined = String::new();
+    for parent in inherits.split(',').map(str::trim) {
+        if children.iter().any(|child| child == parent) {
+            warn(format!(
+                "The {kind} query of '{language}' inherits from '{parent}' in a cycle, so '{parent}' is skipped"
+            ));
+            continue;
+        }
+        // Inherited queries are allowed to be missing, as not every language has every query
+        if let Ok(parent_query) = read_inherited_query(grammars_dir, parent, kind, children) {
+            combined.push_str(&parent_query);
+            combined.push('\n');
+        }
+    }
+    children.pop();
+    combined.push_str(&query);
+    Ok(combined)
+}
+
+/// How highlights are turned into HTML attributes
+#[derive(Clone, Copy)]
+pub enum HighlightMode<'a> {
+    /// As classes, which are styled by the site's stylesheets
+    Classes,
+    /// As inline styles, for feeds and emails where the site's stylesheets aren't loaded
+    InlineStyles(&'a InlineTheme),
+}
+
+impl<'a> HighlightMode<'a> {
+    /// The theme that styles are inlined from, if any
+    pub fn inline_theme(&self) -> Option<&'a InlineTheme> {
+        match self {
+            HighlightMode::Classes => None,
+            HighlightMode::InlineStyles(theme) => Some(theme),
+        }
+    }
+
+    /// Add everything about the mode that affects the HTML to a cache key
+    fn hash(&self, key: &mut KeyHasher) {
         match self {
-            Self::C => &C_CONFIG,
-            Self::Cpp => &CPP_CONFIG,
-            Self::Haskell => &HASKELL_CONFIG,
-            Self::OCaml => &OCAML_CONFIG,
-            Self::Python => &PYTHON_CONFIG,
-            Self::Rust => &RUST_CONFIG,
+            HighlightMode::Classes => {
+                key.input("classes");
+            }
+            HighlightMode::InlineStyles(theme) => {
+                key.input("inline styles");
+                for attrs in &theme.attrs {
+                    key.input(attrs);
+                }
+            }
         }
     }
 }
 
+/// Tree-sitter failed to highlight a piece of code
+#[derive(Debug)]
+pub struct HighlightError {
+    pub source: tree_sitter_highlight::Error,
+    pub lang: String,
+}
+
 /// Highlight the contents of a fenced code block of a given source language as HTML
-pub fn highlight<'src>(lang: CowStr<'src>, code: CowStr<'src>) -> CowStr<'src> {
-    let lang: Language = if let Ok(lang) = lang.parse() {
-        lang
-    } else {
-        return code;
+///
+/// Returns `None` if there is no grammar for the language. The HTML comes from the registry's cache
+/// if the same code has been highlighted the same way with the same grammars before.
+pub fn highlight(
+    registry: &Registry,
+    mode: HighlightMode,
+    lang: &str,
+    code: &str,
+) -> Result<Option<String>, HighlightError> {
+    let Some(config) = registry.get(lang) else {
+        return Ok(None);
     };
-    let config = lang.get_config();
+    // Other languages can be injected into the code, so every grammar goes into the key
+    let cached = registry.cache.as_ref().map(|cache| {
+        let mut key = KeyHasher::default();
+        key.input(registry.fingerprint).input(lang);
+        mode.hash(&mut key);
+        (cache, key.input(code).finish())
+    });
+    if let Some(html) = cached.and_then(|(cache, key)| cache.get(key)) {
+        return Ok(Some(html));
+    }
+
+    let html = registry
+        .render(config, mode, code)
+        .map_err(|source| HighlightError {
+            source,
+            lang: lang.to_string(),
+        })?;
 
-    let mut highlighter = Highlighter::new();
-    let highlights = highlighter
-        .highlight(config, code.as_bytes(), None, |_| None)
-        .unwrap();
+    if let Some((cache, key)) = cached {
+        cache.insert(key, &html);
+    }
+    Ok(Some(html))
+}
 
-    let mut renderer = HtmlRenderer::new();
-    renderer
-        .render(highlights, code.as_bytes(), &|highlight| {
-            HTML_ATTRS[highlight.0].as_bytes()
+#[cfg(feature = "python")]
+#[test]
+fn disables_languages_by_any_name() {
+    let registry = |disabled: &str| {
+        Registry::new(&HighlightConfig {
+            grammars: Some("/nonexistent".into()),
+            aliases: BTreeMap::from([("snake".to_string(), "py".to_string())]),
+            disabled: vec![disabled.to_string()],
+            ..Default::default()
         })
-        .unwrap();
+        .unwrap()
+    };
+    for disabled in ["python", "py", "snake"] {
+        assert!(registry(disabled).get("snake").is_none());
+    }
+    assert!(registry("python").get("py").is_none());
+    assert!(registry("snake").get("py").is_some());
+}
 
-    CowStr::from(String::from_utf8(renderer.html).unwrap())
+#[test]
+fn skips_inheritance_cycles() {
+    let dir = env::temp_dir().join(format!("hyde-query-cycle-{}", std::process::id()));
+    for (language, query) in [("a", "; inherits: b\n(a)"), ("b", "; inherits: a,b\n(b)")] {
+        fs::create_dir_all(dir.join(language)).unwrap();
+        fs::write(dir.join(language).join("highlights.scm"), query).unwrap();
+    }
+    let query = read_query(&dir, "a", "highlights").unwrap();
+    assert_eq!(query, "; inherits: a,b\n(b)\n; inherits: b\n(a)");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bundled_queries_are_valid() {
+    for (_, config) in BUNDLED {
+        Lazy::force(config);
+    }
 }
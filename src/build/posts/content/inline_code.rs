@@ -0,0 +1,80 @@
+//! Finding the languages that inline code spans are annotated with, e.g. `` `Vec<T>`{:rs} ``
+
+use std::iter;
+
+use once_cell::sync::Lazy;
+use pulldown_cmark::{CowStr, Event};
+use regex::Regex;
+
+use crate::InlineCodeSyntax;
+
+static SUFFIX_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\{:([\w+#.-]+)\}").unwrap());
+static INSIDE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{:([\w+#.-]+)\}$").unwrap());
+
+/// Pair every event with the language that it is annotated with, which is only ever given for
+/// [`Event::Code`], removing the annotation from the markdown
+pub fn annotate<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    syntax: InlineCodeSyntax,
+) -> impl Iterator<Item = (Event<'a>, Option<String>)> {
+    let mut events = events.peekable();
+    iter::from_fn(move || {
+        let event = events.next()?;
+        let Event::Code(code) = event else {
+            return Some((event, None));
+        };
+
+        match syntax {
+            InlineCodeSyntax::Suffix => {
+                // The annotation starts the text straight after the code span
+                if let Some(Event::Text(text)) = events.peek_mut() {
+                    if let Some(annotation) = SUFFIX_REGEX.captures(text) {
+                        let lang = annotation[1].to_string();
+                        let rest = text[annotation[0].len()..].to_string();
+                        *text = CowStr::from(rest);
+                        return Some((Event::Code(code), Some(lang)));
+                    }
+                }
+                Some((Event::Code(code), None))
+            }
+            InlineCodeSyntax::Inside => match INSIDE_REGEX.captures(&code) {
+                Some(annotation) => {
+                    let lang = annotation[1].to_string();
+                    let code = code[..code.len() - annotation[0].len()].to_string();
+                    Some((Event::Code(CowStr::from(code)), Some(lang)))
+                }
+                None => Some((Event::Code(code), None)),
+            },
+            InlineCodeSyntax::Off => Some((Event::Code(code), None)),
+        }
+    })
+}
+
+#[test]
+fn finds_annotations() {
+    use pulldown_cmark::Parser;
+
+    let annotated = |markdown, syntax| {
+        annotate(Parser::new(markdown), syntax)
+            .filter_map(|(event, lang)| match event {
+                Event::Code(code) => Some((code.to_string(), lang)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        annotated("`Vec<T>`{:rs} and `x`", InlineCodeSyntax::Suffix),
+        [
+            ("Vec<T>".to_string(), Some("rs".to_string())),
+            ("x".to_string(), None)
+        ]
+    );
+    assert_eq!(
+        annotated("`Vec<T>{:rs}`", InlineCodeSyntax::Inside),
+        [("Vec<T>".to_string(), Some("rs".to_string()))]
+    );
+    assert_eq!(
+        annotated("`Vec<T>`{:rs}", InlineCodeSyntax::Off),
+        [("Vec<T>".to_string(), None)]
+    );
+}
@@ -27,12 +27,17 @@ SOFTWARE.
 
  */
 
-use std::iter::Peekable;
+use std::{borrow::Cow, collections::BTreeMap, ops::Range};
 
+use katex::OutputType;
 use once_cell::sync::Lazy;
-use pulldown_cmark::{Event, Parser};
+use pulldown_cmark::{escape::escape_html, CowStr, Event, Options, Parser, Tag};
 use regex::Regex;
 
+use crate::{MathConfig, MathErrors, MathOutput};
+
+use super::equations::Equations;
+
 const LATEX_DELIMS: [LatexDelim; 8] = [
     LatexDelim::new("$$", "$$", true),
     LatexDelim::new("\\(", "\\)", false),
@@ -48,6 +53,15 @@ const IGNORED_TAGS: [&str; 7] = [
     "script", "noscript", "style", "textarea", "pre", "code", "option",
 ];
 
+/* Maths is swapped out for placeholders of the form `PLACEHOLDER_START <index> PLACEHOLDER_END`
+ * before the markdown is parsed, so that markdown syntax inside of the maths (e.g. `\(`, `\\` and
+ * `*`) isn't mangled by the parser. Characters from the private use area are used as markdown has
+ * no opinions on them. Any `PLACEHOLDER_START` already in a post is escaped by doubling it, so that
+ * it can't be mistaken for a placeholder. */
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+const ESCAPED_PLACEHOLDER_START: &str = "\u{E000}\u{E000}";
+
 struct LatexDelim {
     left: &'static str,
     right: &'static str,
@@ -64,8 +78,13 @@ impl LatexDelim {
     }
 }
 
+/// An iterator adapter that swaps the placeholders left by [`render_maths`] in the text of a post
+/// for the rendered maths they stand for
 pub struct Latexifier<'input, 'callback> {
-    parser: Peekable<Parser<'input, 'callback>>,
+    parser: Parser<'input, 'callback>,
+    /// The HTML of each piece of rendered maths, indexed by placeholder
+    rendered: Vec<String>,
+    /// Events waiting to be yielded, in reverse order
     acc: Vec<Event<'input>>,
 }
 
@@ -79,19 +98,409 @@ enum Node<'input> {
 }
 
 impl<'input, 'callback> Latexifier<'input, 'callback> {
-    fn new(parser: Parser<'input, 'callback>) -> Self {
+    /// Wrap a parser over markdown produced by [`render_maths`], along with the rendered maths
+    pub fn new(parser: Parser<'input, 'callback>, rendered: Vec<String>) -> Self {
         Self {
-            parser: parser.peekable(),
+            parser,
+            rendered,
             acc: Vec::new(),
         }
     }
+
+    /// Split a piece of text at its placeholders, pushing the resulting events onto `acc`
+    fn replace_placeholders(&mut self, text: &str) {
+        let mut events = Vec::new();
+        let mut plain = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find(PLACEHOLDER_START) {
+            plain.push_str(&rest[..start]);
+            let after_start = &rest[start + PLACEHOLDER_START.len_utf8()..];
+            if let Some(after_escape) = after_start.strip_prefix(PLACEHOLDER_START) {
+                plain.push(PLACEHOLDER_START);
+                rest = after_escape;
+                continue;
+            }
+            let maths = after_start.find(PLACEHOLDER_END).and_then(|end| {
+                let html = self
+                    .rendered
+                    .get_mut(after_start[..end].parse::<usize>().ok()?)?;
+                Some((end, std::mem::take(html)))
+            });
+            let (end, html) = match maths {
+                Some(maths) => maths,
+                // Not one of ours, so leave it be
+                None => {
+                    plain.push(PLACEHOLDER_START);
+                    rest = after_start;
+                    continue;
+                }
+            };
+            if !plain.is_empty() {
+                events.push(Event::Text(std::mem::take(&mut plain).into()));
+            }
+            events.push(Event::Html(html.into()));
+            rest = &after_start[end + PLACEHOLDER_END.len_utf8()..];
+        }
+        plain.push_str(rest);
+        if !plain.is_empty() {
+            events.push(Event::Text(plain.into()));
+        }
+        self.acc.extend(events.into_iter().rev());
+    }
 }
 
 impl<'input, 'callback> Iterator for Latexifier<'input, 'callback> {
     type Item = Event<'input>;
     fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+        if let Some(event) = self.acc.pop() {
+            return Some(event);
+        }
+        match self.parser.next()? {
+            Event::Text(text) if text.contains(PLACEHOLDER_START) => {
+                self.replace_placeholders(&text);
+                self.acc.pop()
+            }
+            // Only text can hold maths, anything else just needs unescaping
+            Event::Code(code) => Some(Event::Code(unescape(code))),
+            Event::Html(html) => Some(Event::Html(unescape(html))),
+            Event::Start(Tag::Link(kind, dest, title)) => Some(Event::Start(Tag::Link(
+                kind,
+                unescape(dest),
+                unescape(title),
+            ))),
+            Event::Start(Tag::Image(kind, dest, title)) => Some(Event::Start(Tag::Image(
+                kind,
+                unescape(dest),
+                unescape(title),
+            ))),
+            event => Some(event),
+        }
+    }
+}
+
+/// An error preventing the maths of a post from being rendered
+#[derive(Debug)]
+pub enum MathsError {
+    /// An `\eqref` or a link to an equation used a label that isn't given to any equation
+    UnresolvedLabel { label: String },
+    /// KaTeX rejected a piece of maths, starting at `offset` bytes into the markdown
+    Katex { offset: usize, message: String },
+}
+
+/// The maths of a post, rendered by [`render_maths`]
+pub struct RenderedMaths {
+    /// The markdown, with each piece of maths replaced by a placeholder and any characters that
+    /// could be mistaken for one escaped
+    pub markdown: String,
+    /// The HTML for each placeholder
+    pub html: Vec<String>,
+    /// The maths that KaTeX rejected, if it is configured to only warn about them
+    pub warnings: Vec<MathsError>,
+}
+
+/// A piece of the markdown source of a post, as far as maths is concerned
+enum Piece<'input> {
+    /// Text that may contain `\eqref`s, or maths
+    Node(Node<'input>),
+    /// Code, raw HTML or the contents of an ignored tag
+    Protected(&'input str),
+    /// A link without any text to a fragment, e.g. `[](#eq:foo)`, which may be to an equation
+    Reference {
+        label: &'input str,
+        source: &'input str,
+    },
+}
+
+/// Render all of the maths in the markdown source of a post to HTML using KaTeX, skipping over
+/// code and the contents of [`IGNORED_TAGS`]
+///
+/// Display maths is numbered, and every `\eqref` and link without any text to a labelled equation
+/// is replaced with a link to it. Links without any text to other fragments are left alone, unless
+/// the fragment starts with `eq:`, in which case it must be the label of an equation.
+///
+/// The markdown and the HTML for each of its placeholders are to be passed to a [`Latexifier`].
+pub fn render_maths(markdown: &str, opts: &MathsOpts) -> Result<RenderedMaths, MathsError> {
+    let mut pieces = Vec::new();
+    let mut index = 0;
+    let end = Region {
+        range: markdown.len()..markdown.len(),
+        reference: None,
+    };
+    for region in find_regions(markdown).into_iter().chain([end]) {
+        // Everything between the previous region and this one is free game for maths
+        let text = &markdown[index..region.range.start];
+        pieces.extend(
+            Latexifier::split_at_delims(text)
+                .into_iter()
+                .map(Piece::Node),
+        );
+        let source = &markdown[region.range.clone()];
+        pieces.push(match region.reference {
+            Some(label) => Piece::Reference { label, source },
+            None => Piece::Protected(source),
+        });
+        index = region.range.end;
+    }
+
+    // All of the equations need numbering before anything can refer to them
+    let mut equations = Equations::default();
+    let numbered = pieces
+        .iter()
+        .map(|piece| match piece {
+            Piece::Node(Node::Maths {
+                data,
+                display: true,
+                ..
+            }) => Some(equations.number(data)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let unresolved = |label| MathsError::UnresolvedLabel { label };
+    let mut output = String::with_capacity(markdown.len());
+    let mut rendered = Vec::new();
+    let mut warnings = Vec::new();
+    for (piece, numbered) in pieces.into_iter().zip(numbered) {
+        let html = match piece {
+            Piece::Node(Node::Text(text)) => {
+                let text = equations
+                    .replace_eqrefs(&escape(text), |label, number| {
+                        placeholder(&mut rendered, eqref_html(label, number))
+                    })
+                    .map_err(unresolved)?;
+                output.push_str(&text);
+                continue;
+            }
+            Piece::Node(Node::Maths {
+                data,
+                raw_data,
+                display,
+            }) => {
+                let mut html = String::new();
+                let data = match numbered {
+                    Some(numbered) => {
+                        for label in numbered.labels {
+                            html.push_str(r#"<span class="equation-anchor" id=""#);
+                            escape_html(&mut html, &label).unwrap();
+                            html.push_str(r#""></span>"#);
+                        }
+                        numbered.tex
+                    }
+                    None => data.to_string(),
+                };
+                // KaTeX can't link to other maths, so references are just the equation's text
+                let data = equations
+                    .replace_eqrefs(&data, |_, number| format!("\\textrm{{({number})}}"))
+                    .map_err(unresolved)?;
+                match opts.render(&data, display) {
+                    Ok(maths) => html.push_str(&maths),
+                    Err(message) => {
+                        // The maths is a slice of the markdown, so we can find out where it is
+                        let offset = raw_data.as_ptr() as usize - markdown.as_ptr() as usize;
+                        let err = MathsError::Katex { offset, message };
+                        match opts.on_error {
+                            MathErrors::Fail => return Err(err),
+                            MathErrors::Warn => warnings.push(err),
+                        }
+                        html.push_str(r#"<span class="katex-error">"#);
+                        escape_html(&mut html, raw_data).unwrap();
+                        html.push_str("</span>");
+                    }
+                }
+                html
+            }
+            Piece::Protected(text) => {
+                output.push_str(&escape(text));
+                continue;
+            }
+            Piece::Reference { label, source } => match equations.resolve(label) {
+                Some(number) => eqref_html(label, number),
+                None if label.starts_with("eq:") => return Err(unresolved(label.to_string())),
+                // Just a link to some other part of the post, e.g. `[](#top)`
+                None => {
+                    output.push_str(&escape(source));
+                    continue;
+                }
+            },
+        };
+        output.push_str(&placeholder(&mut rendered, html));
+    }
+
+    Ok(RenderedMaths {
+        markdown: output,
+        html: rendered,
+        warnings,
+    })
+}
+
+/// Get a placeholder to stand in for some rendered HTML
+fn placeholder(rendered: &mut Vec<String>, html: String) -> String {
+    let placeholder = format!("{PLACEHOLDER_START}{}{PLACEHOLDER_END}", rendered.len());
+    rendered.push(html);
+    placeholder
+}
+
+/// Escape any placeholder characters in the source of a post, see [`PLACEHOLDER_START`]
+fn escape(text: &str) -> Cow<'_, str> {
+    if text.contains(PLACEHOLDER_START) {
+        Cow::Owned(text.replace(PLACEHOLDER_START, ESCAPED_PLACEHOLDER_START))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Undo [`escape`] for anything that [`Latexifier`] doesn't search for placeholders
+fn unescape(text: CowStr<'_>) -> CowStr<'_> {
+    if text.contains(ESCAPED_PLACEHOLDER_START) {
+        text.replace(
+            ESCAPED_PLACEHOLDER_START,
+            PLACEHOLDER_START.encode_utf8(&mut [0; 4]),
+        )
+        .into()
+    } else {
+        text
+    }
+}
+
+/// The HTML for a reference to an equation
+fn eqref_html(label: &str, number: &str) -> String {
+    let mut html = String::from(r##"<a class="eqref" href="#"##);
+    escape_html(&mut html, label).unwrap();
+    html.push_str(r#"">("#);
+    escape_html(&mut html, number).unwrap();
+    html.push_str(")</a>");
+    html
+}
+
+/// The KaTeX options that a post's maths is rendered with
+pub struct MathsOpts {
+    inline: katex::Opts,
+    display: katex::Opts,
+    on_error: MathErrors,
+}
+
+impl MathsOpts {
+    /// Combine the site-wide maths config with the macros from a post's frontmatter, the latter
+    /// taking precedence
+    pub fn new(config: &MathConfig, post_macros: &BTreeMap<String, String>) -> Self {
+        let mut opts = katex::Opts::builder()
+            .output_type(match config.output {
+                MathOutput::Html => OutputType::Html,
+                MathOutput::Mathml => OutputType::Mathml,
+                MathOutput::HtmlAndMathml => OutputType::HtmlAndMathml,
+            })
+            .throw_on_error(config.throw_on_error)
+            .build()
+            .unwrap();
+        for (name, definition) in config.macros.iter().chain(post_macros) {
+            opts.add_macro(name.clone(), definition.clone());
+        }
+
+        let inline = opts.clone();
+        opts.set_display_mode(true);
+        Self {
+            inline,
+            display: opts,
+            on_error: config.on_error,
+        }
+    }
+
+    /// Render a single piece of maths, returning KaTeX's error message if it fails
+    fn render(&self, data: &str, display: bool) -> Result<String, String> {
+        let opts = if display { &self.display } else { &self.inline };
+        katex::render_with_opts(data, opts).map_err(|err| match err {
+            // KaTeX's own errors come from the JS it's run in
+            katex::Error::JsExecError(detail) => katex_message(&detail),
+            err => err.to_string(),
+        })
+    }
+}
+
+/// Dig KaTeX's message out of the debug representation of the JS exception, dropping the excerpt
+/// of the maths at the end, which is underlined using combining characters
+fn katex_message(detail: &str) -> String {
+    static MESSAGE_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"KaTeX parse error: (.*?)(?: at position \d+:|"\)$)"#).unwrap());
+
+    match MESSAGE_REGEX.captures(detail) {
+        Some(message) => message[1].replace("\\\\", "\\").replace("\\\"", "\""),
+        None => detail.to_string(),
+    }
+}
+
+/// A range of the markdown that must not be searched for maths
+struct Region<'input> {
+    range: Range<usize>,
+    /// The label referred to, if the region is a link without any text to a fragment
+    reference: Option<&'input str>,
+}
+
+/// Find the regions of the markdown in which maths should not be rendered, i.e. code blocks,
+/// inline code, raw HTML, the contents of any of the [`IGNORED_TAGS`] and references to equations
+///
+/// The regions are returned in order.
+fn find_regions(markdown: &str) -> Vec<Region<'_>> {
+    static IGNORED_TAG_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(&format!(r"(?i)<(/?)(?:{})\b", IGNORED_TAGS.join("|"))).unwrap());
+
+    let mut regions = Vec::new();
+    let protected = |range| Region {
+        range,
+        reference: None,
+    };
+    let mut ignored_depth = 0_usize;
+    let mut in_code_block = false;
+    let mut events = Parser::new_ext(markdown, Options::all())
+        .into_offset_iter()
+        .peekable();
+    while let Some((event, range)) = events.next() {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                regions.push(protected(range));
+            }
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Code(_) => regions.push(protected(range)),
+            Event::Html(html) => {
+                for tag in IGNORED_TAG_REGEX.captures_iter(&html) {
+                    if tag[1].is_empty() {
+                        ignored_depth += 1;
+                    } else {
+                        ignored_depth = ignored_depth.saturating_sub(1);
+                    }
+                }
+                regions.push(protected(range));
+            }
+            _ if ignored_depth > 0 && !in_code_block => regions.push(protected(range)),
+            Event::Start(Tag::Link(_, ref dest, _))
+                if dest.starts_with('#')
+                    && matches!(events.peek(), Some((Event::End(Tag::Link(..)), _))) =>
+            {
+                // Find the label in the source, as `dest` doesn't borrow from it
+                let source = &markdown[range.clone()];
+                let reference = source
+                    .rfind(dest.as_ref())
+                    .map(|index| &source[index + 1..index + dest.len()]);
+                regions.push(Region { range, reference });
+            }
+            _ => (),
+        }
+    }
+
+    // The parser can hand out overlapping ranges (e.g. for the contents of a code block), so they
+    // need to be merged
+    regions.sort_unstable_by_key(|region| region.range.start);
+    let mut merged: Vec<Region> = Vec::with_capacity(regions.len());
+    for region in regions {
+        match merged.last_mut() {
+            Some(last) if region.range.start < last.range.end => {
+                last.range.end = last.range.end.max(region.range.end);
+                last.reference = None;
+            }
+            _ => merged.push(region),
+        }
     }
+    merged
 }
 
 impl<'input, 'callback> Latexifier<'input, 'callback>
@@ -109,7 +518,7 @@ where
         let mut text_view = text;
         let mut index;
         loop {
-            index = if let Some(index) = REGEX_LEFT.find(text) {
+            index = if let Some(index) = REGEX_LEFT.find(text_view) {
                 index.start()
             } else {
                 break;
@@ -144,29 +553,32 @@ where
             });
             text_view = &text_view[(index + delim.right.len())..];
         }
+        if !text_view.is_empty() {
+            data.push(Node::Text(text_view));
+        }
 
         data
     }
 
+    /// Delimiters are all ASCII, so it is safe to work in terms of bytes here, as a delimiter can
+    /// never match part of a multi-byte character
     fn find_end_of_maths(delim: &'static str, text: &str, start_index: usize) -> Option<usize> {
-        let delim_len = delim.len();
+        let (delim, text) = (delim.as_bytes(), text.as_bytes());
 
         let mut brace_level = 0;
-        let mut chars = text.char_indices().peekable();
+        let mut index = start_index;
 
-        while let Some(&(index, ch)) = chars.peek() {
-            match ch {
-                _ if brace_level <= 0 && &text[index..((index) + delim_len)] == delim => {
+        while index < text.len() {
+            match text[index] {
+                _ if brace_level <= 0 && text[index..].starts_with(delim) => {
                     return Some(index);
                 }
-                '\\' => {
-                    chars.next();
-                }
-                '{' => brace_level += 1,
-                '}' => brace_level -= 1,
+                b'\\' => index += 1,
+                b'{' => brace_level += 1,
+                b'}' => brace_level -= 1,
                 _ => (),
             }
-            chars.next();
+            index += 1;
         }
 
         None
@@ -190,3 +602,54 @@ This is some more text
     let parser = Parser::new_ext(source, Options::all());
     parser.for_each(|event| println!("{event:?}"));
 }
+
+#[test]
+fn maths_outside_code_is_rendered() {
+    let source = r#"Inline \(a * b * c\), but not `\(a * b\)` or <code>$$a$$</code>
+
+```
+$$a$$
+```
+"#;
+
+    let opts = MathsOpts::new(&MathConfig::default(), &BTreeMap::new());
+    let RenderedMaths {
+        markdown,
+        html: rendered,
+        ..
+    } = render_maths(source, &opts).unwrap();
+    assert_eq!(rendered.len(), 1);
+    assert!(rendered[0].starts_with(r#"<span class="katex">"#));
+    assert!(markdown.starts_with("Inline \u{E000}0\u{E001}, but not `\\(a * b\\)`"));
+    assert!(markdown.contains("<code>$$a$$</code>"));
+    assert!(markdown.contains("```\n$$a$$\n```"));
+}
+
+#[test]
+fn stray_placeholder_characters_are_kept() {
+    let source = "\u{E000} and \u{E000}0\u{E001} `\u{E000}` \\(a\\)\u{E001}";
+
+    let opts = MathsOpts::new(&MathConfig::default(), &BTreeMap::new());
+    let maths = render_maths(source, &opts).unwrap();
+    let parser = Parser::new_ext(&maths.markdown, Options::all());
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Latexifier::new(parser, maths.html));
+    assert!(html.starts_with(
+        "<p>\u{E000} and \u{E000}0\u{E001} <code>\u{E000}</code> <span class=\"katex\">"
+    ));
+    assert!(html.ends_with("</span>\u{E001}</p>\n"));
+}
+
+#[test]
+fn only_links_to_equations_are_references() {
+    let source = "$$a \\label{eq:a}$$\n\n[](#eq:a) [](#top)\n";
+
+    let opts = MathsOpts::new(&MathConfig::default(), &BTreeMap::new());
+    let maths = render_maths(source, &opts).unwrap();
+    assert!(maths.markdown.ends_with("\n\n\u{E000}1\u{E001} [](#top)\n"));
+    assert!(maths.html[1].contains("href=\"#eq:a\">(1)</a>"));
+    assert!(matches!(
+        render_maths("[](#eq:b)", &opts),
+        Err(MathsError::UnresolvedLabel { label }) if label == "eq:b"
+    ));
+}
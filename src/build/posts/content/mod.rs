@@ -1,45 +1,237 @@
+mod cache;
+mod code_block;
+mod equations;
+mod helix;
 mod highlight;
+mod inline_code;
 mod latex;
+mod terminal;
 
-use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+use pulldown_cmark::{
+    escape::escape_html, html, CodeBlockKind, CowStr, Event, Options, Parser, Tag,
+};
 
-use self::highlight::highlight;
+use std::{collections::BTreeSet, mem, path::Path};
 
-/// Compile the markdown content of a post into HTML
-pub(super) fn compile_content(content_markdown: &str) -> String {
+use crate::{
+    build::{warn, BuildError},
+    frontmatter::Frontmatter,
+    Config, HighlightConfig,
+};
+
+pub(crate) use self::helix::theme_stylesheet;
+pub(super) use self::{
+    cache::Cache,
+    helix::{inline_theme, theme_files, InlineTheme},
+    highlight::Registry,
+};
+
+use self::{
+    code_block::{split_diff, CodeBlockInfo},
+    highlight::{highlight, HighlightError, HighlightMode},
+    latex::{render_maths, Latexifier, MathsError, MathsOpts, RenderedMaths},
+};
+
+/// The content of a post, compiled into HTML
+pub(super) struct Content {
+    /// The HTML for the site's pages, in which code is highlighted with classes
+    pub html: String,
+    /// The HTML for feeds and emails, in which code is highlighted with inline styles, only
+    /// available if the project has a `[highlight] theme`
+    pub html_inline: Option<String>,
+}
+
+/// Compile the markdown content of the post at `path` into HTML, rendering any maths with KaTeX
+///
+/// `first_line` is the (one-indexed) line of the post that the content starts on, for reporting
+/// errors.
+pub(super) fn compile_content(
+    content_markdown: &str,
+    path: &Path,
+    first_line: usize,
+    config: &Config,
+    frontmatter: &Frontmatter,
+    registry: &Registry,
+    inline_theme: Option<&InlineTheme>,
+) -> Result<Content, BuildError> {
+    let maths_error = |err| match err {
+        MathsError::UnresolvedLabel { label } => BuildError::UnresolvedLabel {
+            label,
+            path: path.to_path_buf(),
+        },
+        MathsError::Katex { offset, message } => {
+            let before = &content_markdown[..offset];
+            let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+            BuildError::RenderMaths {
+                message,
+                path: path.to_path_buf(),
+                line: first_line + before.matches('\n').count(),
+                column: before[line_start..].chars().count() + 1,
+            }
+        }
+    };
+    let maths_opts = MathsOpts::new(&config.math, &frontmatter.macros);
+    let mut maths = render_maths(content_markdown, &maths_opts).map_err(maths_error)?;
+    for warning in mem::take(&mut maths.warnings) {
+        warn(maths_error(warning));
+    }
+
+    // The maths is only rendered once, whereas the markdown is rendered for each way of
+    // highlighting code
+    let mut unrecognised_langs = BTreeSet::new();
+    let highlight_error = |HighlightError { source, lang }| BuildError::Highlight {
+        source,
+        lang,
+        path: path.to_path_buf(),
+    };
+    let html = compile_markdown(
+        &maths,
+        registry,
+        HighlightMode::Classes,
+        &config.highlight,
+        &mut unrecognised_langs,
+    )
+    .map_err(highlight_error)?;
+    let html_inline = inline_theme
+        .map(|theme| {
+            compile_markdown(
+                &maths,
+                registry,
+                HighlightMode::InlineStyles(theme),
+                &config.highlight,
+                &mut unrecognised_langs,
+            )
+        })
+        .transpose()
+        .map_err(highlight_error)?;
+
+    if !unrecognised_langs.is_empty() {
+        let langs = unrecognised_langs
+            .into_iter()
+            .collect::<Vec<_>>()
+            .join("', '");
+        warn(format!(
+            "No grammar for the code block languages '{langs}' in the post at '{}', so they won't be highlighted",
+            path.display()
+        ));
+    }
+    Ok(Content { html, html_inline })
+}
+
+/// Compile markdown in which the maths has already been rendered into HTML
+fn compile_markdown(
+    maths: &RenderedMaths,
+    registry: &Registry,
+    mode: HighlightMode,
+    config: &HighlightConfig,
+    unrecognised_langs: &mut BTreeSet<String>,
+) -> Result<String, HighlightError> {
     let options = Options::all();
-    let mut code_block_lang = None;
+    let mut code_block = None;
     let mut fragment_id = None;
-    let parser = Parser::new_ext(content_markdown, options).map(|event| match event {
-        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang))) => {
-            code_block_lang = Some(lang.clone());
-            event
-        }
-        Event::Text(code) if code_block_lang.is_some() => {
-            // We are inside a fenced code block that has a specified source language
-            Event::Html(highlight(code_block_lang.clone().unwrap(), code))
-        }
-        Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
-            code_block_lang = None;
-            event
-        }
-        Event::Start(Tag::Heading(_, frag_id, _)) => {
-            fragment_id = frag_id;
-            event
-        }
-        Event::Text(text) if fragment_id.is_some() => {
-            // We are inside a heading which has a fragment identifier
-            Event::Html(format_heading(fragment_id.unwrap(), text))
-        }
-        Event::End(Tag::Heading(..)) => {
-            fragment_id = None;
-            event
-        }
-        event => event,
-    });
+    // Only the first error is reported, as the events can't be stopped part of the way through
+    let mut error = None;
+    let mut format_code = |lang: &str, code: &str| {
+        format_code_block(registry, mode, lang, code, unrecognised_langs).unwrap_or_else(|err| {
+            error.get_or_insert(err);
+            CowStr::from("")
+        })
+    };
+
+    let parser = Parser::new_ext(&maths.markdown, options);
+    let parser = Latexifier::new(parser, maths.html.clone());
+    let parser =
+        inline_code::annotate(parser, config.inline_code).flat_map(|(event, lang)| match event {
+            Event::Code(code) if lang.is_some() => {
+                let lang = lang.unwrap();
+                let code_html = format_code(&lang, &code);
+                let mut html = String::from("<code class=\"language-");
+                escape_html(&mut html, &lang).unwrap();
+                html.push_str("\">");
+                html.push_str(code_html.trim_end_matches('\n'));
+                html.push_str("</code>");
+                vec![Event::Html(CowStr::from(html))]
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                // The whole code block is rendered once it ends, as it needs to be highlighted as a
+                // whole
+                let mut info = CodeBlockInfo::parse(&info);
+                if info.lang.is_empty() {
+                    info.lang = config.default_language.clone().unwrap_or_default();
+                }
+                code_block = Some((info, String::new()));
+                vec![]
+            }
+            Event::Text(text) if code_block.is_some() => {
+                code_block.as_mut().unwrap().1.push_str(&text);
+                vec![]
+            }
+            Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
+                let (info, code) = code_block.take().unwrap();
+                let (code, diff) = if info.diff {
+                    split_diff(&code)
+                } else {
+                    (code, Vec::new())
+                };
+                let code_html = format_code(&info.lang, &code);
+                vec![Event::Html(CowStr::from(info.to_html(
+                    &code_html,
+                    &diff,
+                    mode.inline_theme(),
+                )))]
+            }
+            Event::Start(Tag::Heading(_, frag_id, _)) => {
+                fragment_id = frag_id;
+                vec![event]
+            }
+            Event::Text(text) if fragment_id.is_some() => {
+                // We are inside a heading which has a fragment identifier
+                vec![Event::Html(format_heading(fragment_id.unwrap(), text))]
+            }
+            Event::End(Tag::Heading(..)) => {
+                fragment_id = None;
+                vec![event]
+            }
+            event => vec![event],
+        });
     let mut html_output = String::new();
     html::push_html(&mut html_output, parser);
-    html_output
+    match error {
+        Some(err) => Err(err),
+        None => Ok(html_output),
+    }
+}
+
+/// Format the contents of a fenced code block, highlighting it if there is a grammar for its
+/// language, otherwise just escaping it
+///
+/// Terminal sessions and ANSI output are handled separately, see [`terminal`]. Languages without a
+/// grammar are added to `unrecognised_langs`, unless highlighting them is disabled.
+fn format_code_block<'a>(
+    registry: &Registry,
+    mode: HighlightMode,
+    lang: &str,
+    code: &str,
+    unrecognised_langs: &mut BTreeSet<String>,
+) -> Result<CowStr<'a>, HighlightError> {
+    match lang {
+        _ if registry.is_disabled(lang) => (),
+        "console" | "shell-session" => {
+            return Ok(CowStr::from(terminal::console(registry, mode, code)?))
+        }
+        "ansi" => return Ok(CowStr::from(terminal::ansi(code, mode.inline_theme()))),
+        _ => {
+            if let Some(html) = highlight(registry, mode, lang, code)? {
+                return Ok(CowStr::from(html));
+            }
+            if !lang.is_empty() {
+                unrecognised_langs.insert(lang.to_string());
+            }
+        }
+    }
+    let mut html = String::new();
+    escape_html(&mut html, code).unwrap();
+    Ok(CowStr::from(html))
 }
 
 /// Format a heading which has an associated fragment identifier as a link
@@ -52,3 +244,43 @@ fn format_heading<'a>(fragment_id: &'a str, heading: CowStr<'a>) -> CowStr<'a> {
     html.push_str(r#"</a>"#);
     CowStr::from(html)
 }
+
+#[test]
+fn unknown_languages_are_escaped() {
+    let registry = Registry::new(&crate::HighlightConfig {
+        grammars: Some("/nonexistent".into()),
+        disabled: vec!["text".to_string()],
+        ..Default::default()
+    })
+    .unwrap();
+    let mut unrecognised_langs = BTreeSet::new();
+
+    let html = format_code_block(
+        &registry,
+        HighlightMode::Classes,
+        "txt",
+        "<b>&</b>",
+        &mut unrecognised_langs,
+    )
+    .unwrap();
+    assert_eq!(&*html, "&lt;b&gt;&amp;&lt;/b&gt;");
+    let html = format_code_block(
+        &registry,
+        HighlightMode::Classes,
+        "",
+        "<b>",
+        &mut unrecognised_langs,
+    )
+    .unwrap();
+    assert_eq!(&*html, "&lt;b&gt;");
+    let html = format_code_block(
+        &registry,
+        HighlightMode::Classes,
+        "text",
+        "<b>",
+        &mut unrecognised_langs,
+    )
+    .unwrap();
+    assert_eq!(&*html, "&lt;b&gt;");
+    assert_eq!(unrecognised_langs, BTreeSet::from(["txt".to_string()]));
+}
@@ -0,0 +1,5 @@
+; The tree-sitter-cpp crate doesn't export its injections query, so it is copied from the crate
+
+(raw_string_literal
+  delimiter: (raw_string_delimiter) @injection.language
+  (raw_string_content) @injection.content)
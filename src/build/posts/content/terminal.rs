@@ -0,0 +1,260 @@
+//! Code blocks of terminal sessions (`console`/`shell-session`) and of output containing ANSI escape
+//! sequences (`ansi`)
+//!
+//! Like highlighted code, the HTML produced here never has an element spanning more than one line.
+
+use pulldown_cmark::escape::escape_html;
+
+use super::{
+    helix::InlineTheme,
+    highlight::{highlight, HighlightError, HighlightMode, Registry},
+};
+
+/// The prompts that start the lines of a terminal session that are commands
+const PROMPTS: [&str; 2] = ["$ ", "# "];
+
+/// Format a terminal session, highlighting commands as shell with their prompts made unselectable,
+/// and styling the output as in [`ansi`]
+pub fn console(
+    registry: &Registry,
+    mode: HighlightMode,
+    code: &str,
+) -> Result<String, HighlightError> {
+    let theme = mode.inline_theme();
+    let mut html = String::new();
+    let mut lines = code.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some(prompt) = PROMPTS.into_iter().find(|&prompt| line.starts_with(prompt)) else {
+            html.push_str("<span class=\"output\">");
+            html.push_str(&ansi(line, theme));
+            html.push_str("</span>\n");
+            continue;
+        };
+
+        // A command can be continued onto the following lines with backslashes, and is
+        // highlighted as a whole
+        let mut command = line[prompt.len()..].to_string();
+        while command.ends_with('\\') {
+            let Some(continuation) = lines.next() else {
+                break;
+            };
+            command.push('\n');
+            command.push_str(continuation);
+        }
+        command.push('\n');
+
+        match theme {
+            Some(theme) => html.push_str(&format!("<span style=\"{}\">", theme.prompt_style)),
+            None => html.push_str("<span class=\"prompt\">"),
+        }
+        escape_html(&mut html, prompt).unwrap();
+        html.push_str("</span>");
+        match highlight(registry, mode, "bash", &command)? {
+            Some(highlighted) => html.push_str(&highlighted),
+            None => escape_html(&mut html, &command).unwrap(),
+        }
+    }
+    Ok(html)
+}
+
+/// The style set by SGR escape sequences, e.g. `\x1b[1;31m`
+#[derive(Clone, Default, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    foreground: Option<Colour>,
+    background: Option<Colour>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Colour {
+    /// One of the 16 standard colours, where 8-15 are the bright versions of 0-7
+    Named(u8),
+    /// Any other colour, from the 256 colour palette or given as RGB
+    Rgb(u8, u8, u8),
+}
+
+const COLOUR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// Format output containing ANSI escape sequences, turning SGR sequences into styled spans and
+/// dropping any others
+///
+/// The spans are styled with classes, or inline from `theme` if one is given.
+pub fn ansi(code: &str, theme: Option<&InlineTheme>) -> String {
+    let mut html = String::new();
+    let mut style = Style::default();
+    let mut rest = code;
+    while !rest.is_empty() {
+        let text_end = rest.find('\x1b').unwrap_or(rest.len());
+        let (text, tail) = rest.split_at(text_end);
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                html.push('\n');
+            }
+            push_styled(&mut html, &style, line, theme);
+        }
+
+        rest = match tail.strip_prefix("\x1b[") {
+            Some(sequence) => {
+                // Control sequences end with a byte in the range `@` to `~`
+                let end = sequence
+                    .find(|c| ('@'..='~').contains(&c))
+                    .unwrap_or(sequence.len());
+                if sequence[end..].starts_with('m') {
+                    style.apply(&sequence[..end]);
+                }
+                sequence.get(end + 1..).unwrap_or_default()
+            }
+            // Any other kind of escape sequence is dropped along with its first character
+            None => {
+                let mut chars = tail.chars();
+                chars.nth(1);
+                chars.as_str()
+            }
+        };
+    }
+    html
+}
+
+fn push_styled(html: &mut String, style: &Style, text: &str, theme: Option<&InlineTheme>) {
+    if text.is_empty() {
+        return;
+    }
+    if *style == Style::default() {
+        escape_html(html, text).unwrap();
+        return;
+    }
+
+    let mut classes = Vec::new();
+    let mut css = String::new();
+    for (set, class, declaration) in [
+        (style.bold, "ansi-bold", "font-weight: bold;"),
+        (style.dim, "ansi-dim", "opacity: 0.7;"),
+        (style.italic, "ansi-italic", "font-style: italic;"),
+        (
+            style.underline,
+            "ansi-underline",
+            "text-decoration: underline;",
+        ),
+    ] {
+        if !set {
+            continue;
+        }
+        match theme {
+            Some(_) => css.push_str(declaration),
+            None => classes.push(class.to_string()),
+        }
+    }
+    for (colour, kind) in [(style.foreground, "fg"), (style.background, "bg")] {
+        let property = if kind == "fg" {
+            "color"
+        } else {
+            "background-color"
+        };
+        match (colour, theme) {
+            (Some(Colour::Named(index)), Some(theme)) => css.push_str(&format!(
+                "{property}: {};",
+                theme.ansi_colours[index as usize]
+            )),
+            (Some(Colour::Named(index @ 0..=7)), None) => {
+                classes.push(format!("ansi-{kind}-{}", COLOUR_NAMES[index as usize]))
+            }
+            (Some(Colour::Named(index)), None) => classes.push(format!(
+                "ansi-{kind}-bright-{}",
+                COLOUR_NAMES[(index - 8) as usize]
+            )),
+            (Some(Colour::Rgb(r, g, b)), _) => {
+                css.push_str(&format!("{property}: #{r:02x}{g:02x}{b:02x};"));
+            }
+            (None, _) => (),
+        }
+    }
+
+    html.push_str("<span");
+    if !classes.is_empty() {
+        html.push_str(&format!(" class=\"{}\"", classes.join(" ")));
+    }
+    if !css.is_empty() {
+        html.push_str(&format!(" style=\"{css}\""));
+    }
+    html.push('>');
+    escape_html(&mut *html, text).unwrap();
+    html.push_str("</span>");
+}
+
+impl Style {
+    /// Apply the parameters of an SGR sequence, e.g. `1;31` from `\x1b[1;31m`
+    fn apply(&mut self, parameters: &str) {
+        let mut parameters = parameters
+            .split([';', ':'])
+            .map(|parameter| parameter.parse::<u8>().unwrap_or(0));
+        // An empty sequence resets the style, just like `0`
+        while let Some(parameter) = parameters.next() {
+            match parameter {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.foreground = Some(Colour::Named(parameter - 30)),
+                38 => self.foreground = extended_colour(&mut parameters),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(Colour::Named(parameter - 40)),
+                48 => self.background = extended_colour(&mut parameters),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(Colour::Named(parameter - 90 + 8)),
+                100..=107 => self.background = Some(Colour::Named(parameter - 100 + 8)),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Parse the rest of a 256 colour (`5;n`) or RGB (`2;r;g;b`) colour parameter
+fn extended_colour(parameters: &mut impl Iterator<Item = u8>) -> Option<Colour> {
+    match parameters.next()? {
+        5 => match parameters.next()? {
+            index @ 0..=15 => Some(Colour::Named(index)),
+            // The 6x6x6 colour cube
+            index @ 16..=231 => {
+                let level = |value: u8| if value == 0 { 0 } else { value * 40 + 55 };
+                let index = index - 16;
+                Some(Colour::Rgb(
+                    level(index / 36),
+                    level(index / 6 % 6),
+                    level(index % 6),
+                ))
+            }
+            // The greyscale ramp
+            index => {
+                let grey = (index - 232) * 10 + 8;
+                Some(Colour::Rgb(grey, grey, grey))
+            }
+        },
+        2 => Some(Colour::Rgb(
+            parameters.next()?,
+            parameters.next()?,
+            parameters.next()?,
+        )),
+        _ => None,
+    }
+}
+
+#[test]
+fn converts_sgr_sequences() {
+    assert_eq!(
+        ansi(
+            "\x1b[1;32m   Compiling\x1b[0m hyde <3\n\x1b[38;5;196merror\x1b[m",
+            None
+        ),
+        "<span class=\"ansi-bold ansi-fg-green\">   Compiling</span> hyde &lt;3\n\
+         <span style=\"color: #ff0000;\">error</span>"
+    );
+}
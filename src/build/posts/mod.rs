@@ -1,19 +1,25 @@
 mod content;
 
 use std::{
+    collections::HashMap,
     fs::{self, File},
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
+use chrono::Utc;
 use serde::Serialize;
-use snafu::ResultExt;
 use upon::TemplateRef;
 
 use crate::{frontmatter::Frontmatter, Config};
 
-use self::content::compile_content;
+use self::content::{compile_content, inline_theme, theme_files, Cache, InlineTheme, Registry};
 
-use super::{engine::Engine, read_dir, BuildError, BuildRes, ParseFrontmatterSnafu};
+pub(super) use self::content::theme_stylesheet;
+
+use super::{
+    engine::Engine, permalink::Permalink, read_dir, warn, BuildError, BuildOptions, BuildRes,
+};
 
 /// All of the required information about a given post
 #[derive(Clone, Serialize)]
@@ -25,6 +31,9 @@ struct Post {
     pub frontmatter: Frontmatter,
     /// The main content of the post, rendered as html
     pub content: String,
+    /// The content with code highlighted using inline styles rather than classes, for feeds and
+    /// emails, only available if the project has a `[highlight] theme`
+    pub content_inline: Option<String>,
 }
 
 /// Information to be passed to the `post.html` template for each post
@@ -34,37 +43,42 @@ struct PostInfo<'a> {
     post: &'a Post,
     #[serde(flatten)]
     config: &'a Config,
+    /// The URL of the post relative to the root of the site
+    url: &'a str,
+    /// The relative URL of the root of the site from the post, e.g. `../`, for linking to the
+    /// theme's stylesheets and scripts
+    root: String,
 }
 
 impl Post {
-    /// Parse a post, which consists of frontmatter and content
-    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, BuildError> {
-        let path = path.as_ref();
-        let source = fs::read_to_string(path).map_err(|err| (err, path.to_path_buf()))?;
-
-        let mut sections = source.splitn(3, "---");
-
-        let frontmatter_source = sections
-            .nth(1)
-            .ok_or_else(|| BuildError::MissingFrontmatter {
-                path: path.to_path_buf(),
-            })?;
-        let frontmatter =
-            serde_yaml::from_str(frontmatter_source).with_context(|_| ParseFrontmatterSnafu {
-                path: path.to_path_buf(),
-            })?;
-
-        let content_markdown = sections
-            .next()
-            .ok_or_else(|| BuildError::MissingFrontmatter {
-                path: path.to_path_buf(),
-            })?;
-        let content = compile_content(content_markdown);
+    /// Compile a post from its source, whose frontmatter has already been parsed, with its content
+    /// starting at the byte offset `content_start`
+    pub fn new(
+        path: &Path,
+        source: &str,
+        frontmatter: Frontmatter,
+        content_start: usize,
+        config: &Config,
+        registry: &Registry,
+        inline_theme: Option<&InlineTheme>,
+    ) -> Result<Self, BuildError> {
+        let content_markdown = &source[content_start..];
+        let first_line = source[..content_start].matches('\n').count() + 1;
+        let content = compile_content(
+            content_markdown,
+            path,
+            first_line,
+            config,
+            &frontmatter,
+            registry,
+            inline_theme,
+        )?;
 
         Ok(Self {
             path: path.to_path_buf(),
             frontmatter,
-            content,
+            content: content.html,
+            content_inline: content.html_inline,
         })
     }
 
@@ -72,14 +86,20 @@ impl Post {
     pub fn render(
         &self,
         config: &Config,
+        permalink: &Permalink,
         post_dest: impl AsRef<Path>,
         template: TemplateRef,
     ) -> BuildRes {
         let post_dest = post_dest.as_ref();
-        dbg!(post_dest);
         let writer = File::create(post_dest).map_err(|err| (err, post_dest.to_path_buf()))?;
+        let info = PostInfo {
+            post: self,
+            config,
+            url: permalink.url(),
+            root: permalink.root(),
+        };
         template
-            .render(PostInfo { post: self, config })
+            .render(info)
             .to_writer(writer)
             .map_err(|err| BuildError::RenderPost {
                 source: Box::new(err),
@@ -88,48 +108,123 @@ impl Post {
     }
 }
 
-/// Compile posts in the project's `posts/` directory to HTML, storing results in `static/posts/`
+/// Compile posts in the project's `posts/` directory to HTML, storing results in `static/` at their
+/// permalinks, and returning the paths of the results
 ///
 /// # Details
 ///
-/// If a corresponding HTML file does not exist for a post, or the post source is newer than the
-/// HTML outout, then the post will be compiled, otherwise no action will be taken for that post
+/// If a corresponding HTML file does not exist for a post, or either the post source, the config,
+/// any of the theme's templates or the `[highlight] theme` files are newer than the HTML output,
+/// then the post will be compiled, otherwise no action will be taken for that post
 ///
 /// # Panics
 ///
 /// If the `post.html` template has not been loaded into `engine`, a panic will occur
-pub fn compile_posts(config: &Config, engine: &Engine, dir: impl AsRef<Path>) -> BuildRes {
+pub fn compile_posts(
+    config: &Config,
+    engine: &Engine,
+    dir: impl AsRef<Path>,
+    options: &BuildOptions,
+) -> Result<Vec<PathBuf>, BuildError> {
     let dir = dir.as_ref();
     let posts_dir = dir.join("posts");
-    let static_posts_dir = dir.join("static/posts");
-    fs::create_dir_all(&static_posts_dir).map_err(|err| (err, posts_dir.clone()))?;
+    let static_dir = dir.join("static");
 
     let post_template = engine.get_post();
+    let dependencies_modified = newest_dependency(config, dir)?;
+    let registry = Registry::new(&config.highlight)?.with_cache(Cache::open(dir));
+    let inline_theme = config
+        .highlight
+        .theme
+        .as_ref()
+        .map(|theme| inline_theme(dir, theme))
+        .transpose()?;
+    let now = Utc::now();
+    // The post that each result comes from, to catch posts with the same permalink
+    let mut outputs = HashMap::new();
+    let mut all_compiled = true;
     for post in read_dir(&posts_dir, &[])? {
         let post_path = post.path();
         let post_metadata = post.metadata().map_err(|err| (err, post_path.clone()))?;
-        let html_path = {
-            let mut html_filename = post_path
-                .file_stem()
-                .expect("missing filename")
-                .to_os_string();
-            html_filename.push(".html");
-            static_posts_dir.join(html_filename)
-        };
+        // The source is only read once, so that the post that is checked is the one compiled
+        let source = fs::read_to_string(&post_path).map_err(|err| (err, post_path.clone()))?;
+        let (frontmatter, content_start, defaults) =
+            Frontmatter::parse(&source, &post_path, config)?;
+        let permalink = Permalink::new(&config.permalink, &frontmatter, &post_path)?;
+        let html_path = permalink.output_path(&static_dir);
+        // Anything that excluded posts compiled to before, e.g. drafts built by `hyde serve`, or
+        // posts that have since expired, is cleaned up along with the rest of `static/`
+        if !options.includes(frontmatter.status(now)) {
+            continue;
+        }
+        if let Some(other) = outputs.insert(html_path.clone(), post_path.clone()) {
+            return Err(BuildError::DuplicatePermalink {
+                url: permalink.url().to_string(),
+                path: post_path,
+                other,
+            });
+        }
+
         // If the corresponding HTML file exists then re-compile the post if it is newer, otherwise
         // do nothing
         if html_path.exists() {
             let html_metadata = html_path.metadata().expect("Failed to get file metadata");
-            if post_metadata.modified().unwrap() > html_metadata.modified().unwrap() {
-                let post = Post::from_path(&post_path)?;
-                post.render(config, html_path, post_template)?;
+            let html_modified = html_metadata.modified().unwrap();
+            if post_metadata.modified().unwrap() <= html_modified
+                && dependencies_modified <= html_modified
+            {
+                all_compiled = false;
+                continue;
             }
         // If it does not exist then just compile the post
         } else {
-            let post = Post::from_path(&post_path)?;
-            post.render(config, html_path, post_template)?;
+            let html_dir = html_path.parent().expect("missing parent directory");
+            fs::create_dir_all(html_dir).map_err(|err| (err, html_dir.to_path_buf()))?;
+        }
+
+        // Defaults are only warned about when the post is compiled, so not on every build
+        for default in defaults {
+            warn(format!("The post at '{}' {default}", post_path.display()));
         }
+        let post = Post::new(
+            &post_path,
+            &source,
+            frontmatter,
+            content_start,
+            config,
+            &registry,
+            inline_theme.as_ref(),
+        )?;
+        post.render(config, &permalink, &html_path, post_template)?;
+    }
+
+    // Only a build of every post knows which highlighted code is still needed
+    if let Some(cache) = registry.cache().filter(|_| all_compiled) {
+        cache.evict_unused();
     }
 
-    Ok(())
+    Ok(outputs.into_keys().collect())
+}
+
+/// Get the most recent modification time out of everything that every post's output depends on,
+/// i.e. the `hyde.toml` config file, the theme's templates and the `[highlight] theme` files
+fn newest_dependency(config: &Config, dir: &Path) -> Result<SystemTime, BuildError> {
+    let mut dependencies = vec![dir.join("hyde.toml")];
+    for template in read_dir(&config.theme.join("templates"), &[])? {
+        dependencies.push(template.path());
+    }
+    if let Some(theme) = &config.highlight.theme {
+        dependencies.extend(theme_files(dir, theme)?);
+    }
+
+    let mut newest = SystemTime::UNIX_EPOCH;
+    for path in dependencies {
+        let modified = path
+            .metadata()
+            .map_err(|err| (err, path))?
+            .modified()
+            .unwrap();
+        newest = newest.max(modified);
+    }
+    Ok(newest)
 }
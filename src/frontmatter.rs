@@ -1,14 +1,434 @@
-use chrono::{DateTime, FixedOffset};
-use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, ops::Range, path::Path};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, SubsecRound, Utc};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+
+use crate::{build::BuildError, Config};
+
+/// The formats that frontmatter can be written in, along with the fences that go on the lines
+/// before and after them
+///
+/// JSON frontmatter isn't fenced, it is just an object at the very start of the post.
+const FENCES: &[(&str, Format)] = &[("---", Format::Yaml), ("+++", Format::Toml)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Yaml,
+    Toml,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct Frontmatter {
     /// The full title of the post
     pub title: String,
-    /// Format: RFC 3339 (parsed by [`chrono::DateTime::parse_from_rfc3339`])
+    /// Format: RFC 3339 (parsed by [`chrono::DateTime::parse_from_rfc3339`]), or just a date, which
+    /// is taken to be midnight UTC
+    ///
+    /// Can also be given as `date`, as in Zola and Hugo. Defaults to the date that the filename
+    /// starts with (e.g. `2023-10-24-hello.md`), otherwise when the post was last modified.
     pub timestamp: DateTime<FixedOffset>,
     /// Format: RFC 5464 (i.e. the `lang` attribute of the `html` tag)
+    ///
+    /// Defaults to the `default_language` in the config.
     pub language: String,
     /// A list of topics that the post is related to
     pub tags: Vec<String>,
+    /// KaTeX macros for this post only, on top of those in the config
+    pub macros: BTreeMap<String, String>,
+    /// Whether the post is unfinished, in which case it is only built by `hyde build --drafts` and
+    /// `hyde serve`
+    pub draft: bool,
+    /// When the post stops being published, in the same format as `timestamp`
+    pub expires: Option<DateTime<FixedOffset>>,
+    /// The post's part of its URL, see the `permalink` in the config
+    pub slug: Option<String>,
+    /// Every other key, for the theme's templates to use as they like, e.g.
+    /// `{{ frontmatter.extra.cover_image }}` in `post.html`, or `{{ frontmatter.extra?.cover_image }}`
+    /// if not every post has one
+    ///
+    /// In `index.html` the frontmatter of each of the `recent_posts` is flattened into it, so the
+    /// same key is `{{ post.extra.cover_image }}`.
+    ///
+    /// The keys of an `extra` table (as in Zola) are included too, but a key given both inside and
+    /// outside of it takes the value from outside. An `extra` that isn't a table is kept as it is,
+    /// under `extra`.
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Whether a post is published, as of some point in time
+///
+/// A post can be held back for more than one reason, e.g. a draft that is also scheduled, and is
+/// only published if it is held back for none of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Status {
+    /// Marked as a draft
+    pub draft: bool,
+    /// Its timestamp is yet to come
+    pub scheduled: bool,
+    /// It has passed its `expires` time
+    pub expired: bool,
+}
+
+impl Status {
+    /// Whether the post isn't held back for any reason
+    pub fn is_published(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Frontmatter {
+    /// Whether the post is published as of `now`
+    pub fn status(&self, now: DateTime<Utc>) -> Status {
+        Status {
+            draft: self.draft,
+            scheduled: self.timestamp > now,
+            expired: self.expires.is_some_and(|expires| expires <= now),
+        }
+    }
+}
+
+/// The frontmatter as it is written in the post, before the defaults are filled in
+#[derive(Deserialize)]
+struct RawFrontmatter {
+    title: String,
+    #[serde(alias = "date", default, deserialize_with = "deserialize_timestamp")]
+    timestamp: Option<DateTime<FixedOffset>>,
+    language: Option<String>,
+    /// An empty `tags:` in YAML is null rather than an empty list
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    tags: Vec<String>,
+    #[serde(default)]
+    macros: BTreeMap<String, String>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    expires: Option<DateTime<FixedOffset>>,
+    slug: Option<String>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+impl Frontmatter {
+    /// Parse the frontmatter at the start of the source of the post at `path`, returning it along
+    /// with the byte offset that the content of the post starts at, and a description of each
+    /// default that was used for a missing field
+    ///
+    /// The frontmatter is either YAML fenced by `---`, TOML fenced by `+++`, or a JSON object.
+    pub fn parse(
+        source: &str,
+        path: &Path,
+        config: &Config,
+    ) -> Result<(Self, usize, Vec<String>), BuildError> {
+        let (raw, content) = parse_raw(source, path)?;
+        let mut defaults = Vec::new();
+
+        let language = match (raw.language, &config.default_language) {
+            (Some(language), _) => language,
+            (None, Some(language)) => {
+                defaults.push(format!(
+                    "has no `language`, so it defaults to '{language}' from `default_language`"
+                ));
+                language.clone()
+            }
+            (None, None) => {
+                return Err(BuildError::MissingLanguage {
+                    path: path.to_path_buf(),
+                })
+            }
+        };
+
+        let timestamp = match raw.timestamp {
+            Some(timestamp) => timestamp,
+            None => {
+                let (timestamp, origin) = match filename_date(path) {
+                    Some((date, _)) => (
+                        utc(date.and_hms_opt(0, 0, 0).unwrap()),
+                        "the date in its filename",
+                    ),
+                    None => {
+                        let modified = fs::metadata(path)
+                            .and_then(|metadata| metadata.modified())
+                            .map_err(|err| (err, path.to_path_buf()))?;
+                        (
+                            utc(DateTime::<Utc>::from(modified).naive_utc().trunc_subsecs(0)),
+                            "when it was last modified",
+                        )
+                    }
+                };
+                defaults.push(format!(
+                    "has no `timestamp`, so it defaults to {origin}, {}",
+                    timestamp.to_rfc3339()
+                ));
+                timestamp
+            }
+        };
+
+        let mut extra = raw.extra;
+        match extra.remove("extra") {
+            Some(serde_yaml::Value::Mapping(nested)) => {
+                for (key, value) in nested {
+                    if let serde_yaml::Value::String(key) = key {
+                        extra.entry(key).or_insert(value);
+                    }
+                }
+            }
+            Some(value) => {
+                extra.insert("extra".to_string(), value);
+            }
+            None => (),
+        }
+
+        let frontmatter = Self {
+            title: raw.title,
+            timestamp,
+            language,
+            tags: raw.tags,
+            macros: raw.macros,
+            draft: raw.draft,
+            expires: raw.expires,
+            slug: raw.slug,
+            extra,
+        };
+        Ok((frontmatter, content, defaults))
+    }
+}
+
+/// Parse the frontmatter as it is written in the post, returning it along with the byte offset of
+/// the content
+fn parse_raw(source: &str, path: &Path) -> Result<(RawFrontmatter, usize), BuildError> {
+    if source.starts_with('{') {
+        let mut objects = serde_json::Deserializer::from_str(source).into_iter();
+        let frontmatter = objects
+            .next()
+            .expect("the source isn't empty")
+            .map_err(|source| BuildError::ParseJsonFrontmatter {
+                source,
+                path: path.to_path_buf(),
+            })?;
+        return Ok((frontmatter, objects.byte_offset()));
+    }
+
+    let (format, frontmatter, content) =
+        split(source).ok_or_else(|| BuildError::MissingFrontmatter {
+            path: path.to_path_buf(),
+        })?;
+    let frontmatter = &source[frontmatter];
+    let frontmatter = match format {
+        Format::Yaml => {
+            serde_yaml::from_str(frontmatter).map_err(|source| BuildError::ParseFrontmatter {
+                source,
+                path: path.to_path_buf(),
+            })?
+        }
+        Format::Toml => {
+            parse_toml(frontmatter).map_err(|source| BuildError::ParseTomlFrontmatter {
+                source,
+                path: path.to_path_buf(),
+            })?
+        }
+    };
+    Ok((frontmatter, content))
+}
+
+/// Parse TOML frontmatter, in which dates and times are their own type rather than strings
+fn parse_toml(frontmatter: &str) -> Result<RawFrontmatter, toml::de::Error> {
+    let mut frontmatter: toml::Value = toml::from_str(frontmatter)?;
+    stringify_datetimes(&mut frontmatter);
+    frontmatter.try_into()
+}
+
+/// Replace every date and time in a TOML value with a string, as everything else takes them to be
+fn stringify_datetimes(value: &mut toml::Value) {
+    match value {
+        toml::Value::Datetime(datetime) => *value = toml::Value::String(datetime.to_string()),
+        toml::Value::Array(values) => values.iter_mut().for_each(stringify_datetimes),
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                stringify_datetimes(value);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Deserialize a timestamp that is either RFC 3339, a date and time without an offset, or just a
+/// date, the latter two being taken as UTC
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<DateTime<FixedOffset>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(timestamp) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    DateTime::parse_from_rfc3339(&timestamp)
+        .or_else(|err| {
+            NaiveDateTime::parse_from_str(&timestamp, "%Y-%m-%dT%H:%M:%S%.f")
+                .or_else(|_| {
+                    NaiveDate::parse_from_str(&timestamp, "%Y-%m-%d")
+                        .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+                })
+                .map(utc)
+                .map_err(|_| err)
+        })
+        .map(Some)
+        .map_err(|err| D::Error::custom(format!("invalid timestamp '{timestamp}': {err}")))
+}
+
+/// Deserialize a value that may be null, e.g. an empty list in YAML, as its default
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// The date that the filename of a post starts with, along with the rest of the filename without
+/// its extension, e.g. `2023-10-24` and `-hello` for `2023-10-24-hello.md`
+pub fn filename_date(path: &Path) -> Option<(NaiveDate, &str)> {
+    let stem = path.file_stem()?.to_str()?;
+    let rest = stem.get(10..)?;
+    if !(rest.is_empty() || rest.starts_with('-')) {
+        return None;
+    }
+    let date = NaiveDate::parse_from_str(&stem[..10], "%Y-%m-%d").ok()?;
+    Some((date, rest))
+}
+
+fn utc(datetime: NaiveDateTime) -> DateTime<FixedOffset> {
+    datetime.and_utc().fixed_offset()
+}
+
+/// Split the source of a post into the format of its frontmatter, the byte range of the
+/// frontmatter and the offset of its content
+///
+/// The frontmatter must start on the very first line of the source, and is fenced by lines
+/// consisting of just `---` (or `+++`), so that later `---`s (e.g. horizontal rules) aren't
+/// mistaken for fences. The range starts with the newline that ends the opening fence, so that the
+/// lines of the frontmatter line up with those of the post in errors.
+fn split(source: &str) -> Option<(Format, Range<usize>, usize)> {
+    let mut lines = source.split_inclusive('\n');
+    let first_line = lines.next()?.trim_end();
+    let &(fence, format) = FENCES.iter().find(|(fence, _)| *fence == first_line)?;
+
+    let start = source.find('\n')?;
+    let mut end = start + 1;
+    for line in lines {
+        if line.trim_end() == fence {
+            return Some((format, start..end, end + line.len()));
+        }
+        end += line.len();
+    }
+    None
+}
+
+#[test]
+fn splits_on_fences() {
+    let source = "---\ntitle: a --- b\n---\nabove\n\n---\n\nbelow\n";
+    let (format, frontmatter, content) = split(source).unwrap();
+    assert_eq!(format, Format::Yaml);
+    assert_eq!(&source[frontmatter], "\ntitle: a --- b\n");
+    assert_eq!(&source[content..], "above\n\n---\n\nbelow\n");
+
+    let source = "+++\r\ntitle = 'a'\r\n---\r\n+++";
+    let (format, frontmatter, content) = split(source).unwrap();
+    assert_eq!(format, Format::Toml);
+    assert_eq!(&source[frontmatter], "\ntitle = 'a'\r\n---\r\n");
+    assert_eq!(&source[content..], "");
+
+    assert!(split("\n---\ntitle: a\n---\n").is_none());
+    assert!(split("---\ntitle: a\n").is_none());
+    assert!(split("----\ntitle: a\n---\n").is_none());
+}
+
+#[test]
+fn parses_every_format() {
+    let sources = [
+        "---\ntitle: A\ntimestamp: 2023-10-24T00:00:00Z\nlanguage: en\ntags: [b]\n---\nHi\n",
+        "+++\ntitle = 'A'\ndate = 2023-10-24\nlanguage = 'en'\ntags = ['b']\n+++\nHi\n",
+        "{\"title\": \"A\", \"date\": \"2023-10-24T00:00:00\", \"language\": \"en\", \"tags\": [\"b\"]}\nHi\n",
+    ];
+    for source in sources {
+        let (frontmatter, content) = parse_raw(source, Path::new("post.md")).unwrap();
+        assert_eq!(frontmatter.title, "A");
+        assert_eq!(
+            frontmatter.timestamp,
+            DateTime::parse_from_rfc3339("2023-10-24T00:00:00Z").ok()
+        );
+        assert_eq!(frontmatter.language.as_deref(), Some("en"));
+        assert_eq!(frontmatter.tags, ["b"]);
+        assert_eq!(source[content..].trim(), "Hi");
+    }
+
+    let (frontmatter, _) = parse_raw("---\ntitle: A\ntags:\n---\n", Path::new("post.md")).unwrap();
+    assert_eq!(frontmatter.timestamp, None);
+    assert_eq!(frontmatter.language, None);
+    assert!(frontmatter.tags.is_empty());
+
+    let source = "+++\ntitle = 'A'\ncover = 'a.png'\n[extra]\nupdated = 2024-01-01\n+++\n";
+    let (frontmatter, _) = parse_raw(source, Path::new("post.md")).unwrap();
+    assert_eq!(
+        serde_yaml::to_string(&frontmatter.extra).unwrap(),
+        "cover: a.png\nextra:\n  updated: 2024-01-01\n"
+    );
+}
+
+#[test]
+fn finds_dates_in_filenames() {
+    let date = |path| filename_date(Path::new(path));
+    let october = NaiveDate::from_ymd_opt(2023, 10, 24).unwrap();
+    assert_eq!(date("posts/2023-10-24-hello.md"), Some((october, "-hello")));
+    assert_eq!(date("2023-10-24.md"), Some((october, "")));
+    assert_eq!(date("2023-10-245.md"), None);
+    assert_eq!(date("hello.md"), None);
+}
+
+#[test]
+fn statuses() {
+    let at = |timestamp| DateTime::parse_from_rfc3339(timestamp).unwrap();
+    let now = at("2024-01-02T00:00:00Z").with_timezone(&Utc);
+    let mut frontmatter = Frontmatter {
+        title: String::new(),
+        timestamp: at("2024-01-01T00:00:00Z"),
+        language: String::new(),
+        tags: Vec::new(),
+        macros: BTreeMap::new(),
+        draft: false,
+        expires: Some(at("2024-01-03T00:00:00Z")),
+        slug: None,
+        extra: BTreeMap::new(),
+    };
+    let status = |draft, scheduled, expired| Status {
+        draft,
+        scheduled,
+        expired,
+    };
+    assert!(frontmatter.status(now).is_published());
+    frontmatter.expires = Some(at("2024-01-02T00:00:00Z"));
+    assert_eq!(frontmatter.status(now), status(false, false, true));
+    frontmatter.draft = true;
+    assert_eq!(frontmatter.status(now), status(true, false, true));
+    frontmatter.expires = None;
+    frontmatter.timestamp = at("2024-01-02T00:00:01Z");
+    assert_eq!(frontmatter.status(now), status(true, true, false));
+    frontmatter.draft = false;
+    assert_eq!(frontmatter.status(now), status(false, true, false));
+}
+
+#[test]
+fn merges_extra_tables() {
+    let config: Config = toml::from_str("name = 'a'\ndescription = ''\ntheme = 'theme'\n").unwrap();
+    let parse = |source| {
+        let (frontmatter, _, _) =
+            Frontmatter::parse(source, Path::new("post.md"), &config).unwrap();
+        serde_yaml::to_string(&frontmatter.extra).unwrap()
+    };
+    assert_eq!(
+        parse("+++\ntitle = 'A'\ndate = 2024-01-01\nlanguage = 'en'\ncover = 'a.png'\n[extra]\ncover = 'b.png'\nupdated = 2024-01-01\n+++\n"),
+        "cover: a.png\nupdated: 2024-01-01\n"
+    );
+    assert_eq!(
+        parse("---\ntitle: A\ndate: 2024-01-01\nlanguage: en\nextra: 5\n---\n"),
+        "extra: 5\n"
+    );
 }
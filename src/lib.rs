@@ -1,14 +1,152 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
 pub mod build;
 mod frontmatter;
 pub mod new;
+pub mod serve;
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub name: String,
     pub description: String,
     pub theme: PathBuf,
+    /// The language of posts that don't give their own `language` in their frontmatter
+    pub default_language: Option<String>,
+    /// The pattern of the URLs of posts, in which `:year`, `:month`, `:day` and `:slug` are filled
+    /// in for each post, e.g. `/:year/:month/:slug/`
+    ///
+    /// A URL ending in a slash gets a directory of its own with an `index.html` in it. The pattern
+    /// mustn't have `.` or `..` parts, so that every post ends up in `static/`.
+    #[serde(default = "default_permalink")]
+    pub permalink: String,
+    /// Options for rendering maths with KaTeX
+    #[serde(default)]
+    pub math: MathConfig,
+    /// Options for syntax highlighting code blocks
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+}
+
+/// The `[math]` section of the config
+#[derive(Serialize, Deserialize)]
+pub struct MathConfig {
+    /// Macros available to the maths in every post, e.g. `"\\R" = "\\mathbb{R}"`
+    #[serde(default)]
+    pub macros: BTreeMap<String, String>,
+    /// The markup KaTeX should output
+    #[serde(default)]
+    pub output: MathOutput,
+    /// Whether KaTeX should fail on invalid maths, rather than rendering its source in red
+    #[serde(default = "default_throw_on_error")]
+    pub throw_on_error: bool,
+    /// What to do when KaTeX fails on a piece of maths, only relevant if `throw_on_error` is set
+    #[serde(default)]
+    pub on_error: MathErrors,
+}
+
+impl Default for MathConfig {
+    fn default() -> Self {
+        Self {
+            macros: BTreeMap::new(),
+            output: MathOutput::default(),
+            throw_on_error: default_throw_on_error(),
+            on_error: MathErrors::default(),
+        }
+    }
+}
+
+fn default_permalink() -> String {
+    "/posts/:slug.html".to_string()
+}
+
+fn default_throw_on_error() -> bool {
+    true
+}
+
+/// The `[highlight]` section of the config
+#[derive(Default, Serialize, Deserialize)]
+pub struct HighlightConfig {
+    /// The directory to load extra tree-sitter grammars from, defaulting to
+    /// `$XDG_CONFIG_HOME/hyde/grammars`
+    pub grammars: Option<PathBuf>,
+    /// The helix theme to generate `static/highlight.css` from, if any
+    pub theme: Option<HighlightTheme>,
+    /// How inline code is annotated with the language to highlight it as
+    #[serde(default)]
+    pub inline_code: InlineCodeSyntax,
+    /// Extra names for languages in code blocks, e.g. `h = "c"`, which take precedence over the
+    /// built in ones
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// The language of fenced code blocks without an info string
+    pub default_language: Option<String>,
+    /// The languages of code blocks that are never highlighted, e.g. `["text"]`
+    ///
+    /// Disabling a language by the name of its grammar, e.g. `rust`, disables its aliases too.
+    #[serde(default)]
+    pub disabled: Vec<String>,
+}
+
+/// How inline code is annotated with the language to highlight it as
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InlineCodeSyntax {
+    /// Straight after the code, e.g. `` `Vec<T>`{:rs} ``
+    #[default]
+    Suffix,
+    /// At the end of the code, e.g. `` `Vec<T>{:rs}` ``
+    Inside,
+    /// Inline code isn't highlighted
+    Off,
+}
+
+/// The helix themes to generate the stylesheet for highlighted code from, relative to the project
+/// directory
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HighlightTheme {
+    /// A single theme for both the light and dark themes, e.g. `theme = "onedark.toml"`
+    Single(PathBuf),
+    /// Separate light and dark themes, e.g. `theme = { light = "..", dark = ".." }`
+    Variants { light: PathBuf, dark: PathBuf },
+}
+
+impl HighlightTheme {
+    pub fn light(&self) -> &Path {
+        match self {
+            Self::Single(path) | Self::Variants { light: path, .. } => path,
+        }
+    }
+
+    pub fn dark(&self) -> &Path {
+        match self {
+            Self::Single(path) | Self::Variants { dark: path, .. } => path,
+        }
+    }
+}
+
+/// The markup that KaTeX renders maths to, named as in KaTeX's own options
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MathOutput {
+    Html,
+    Mathml,
+    #[default]
+    HtmlAndMathml,
+}
+
+/// What to do about maths that KaTeX fails to render
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MathErrors {
+    /// Fail the build
+    #[default]
+    Fail,
+    /// Warn about it and leave the source of the maths in the post
+    Warn,
 }
@@ -1,9 +1,14 @@
-use std::{env, io, path::PathBuf};
+use std::{
+    env, fs, io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
 use clap::{Parser, Subcommand};
 use hyde::{
-    build,
+    build::{self, BuildOptions},
     new::{self, CreateError},
+    serve, HighlightTheme,
 };
 use snafu::Snafu;
 
@@ -27,12 +32,47 @@ enum Command {
         desc: Option<String>,
     },
     /// Build the project in the current working directory
-    Build,
+    Build {
+        /// Include posts marked as drafts
+        #[arg(long)]
+        drafts: bool,
+        /// Include posts with timestamps in the future
+        #[arg(long)]
+        future: bool,
+    },
     /// Build and serve the resulting statically generated site.
-    Serve,
+    Serve {
+        /// The address to serve the site on
+        #[arg(long, default_value = "127.0.0.1")]
+        address: IpAddr,
+        /// The port to serve the site on
+        #[arg(short, long, default_value_t = 8080)]
+        port: u16,
+    },
+    /// List the posts of the project in the current working directory, and whether they are
+    /// published
+    List {
+        /// Only list posts with timestamps in the future, which are yet to be published
+        #[arg(long)]
+        scheduled: bool,
+    },
+    /// Generate the stylesheet for highlighted code from helix themes, as `hyde build` does
+    HighlightCss {
+        /// The helix theme to use for both the light and dark themes, defaulting to the project's
+        /// `[highlight] theme`
+        theme: Option<PathBuf>,
+        /// The helix theme to use for the light theme instead
+        #[arg(long)]
+        light: Option<PathBuf>,
+        /// The helix theme to use for the dark theme instead
+        #[arg(long)]
+        dark: Option<PathBuf>,
+        /// Where to write the stylesheet, or `-` to print it
+        #[arg(short, long, default_value = "static/highlight.css")]
+        output: PathBuf,
+    },
 }
 
-#[allow(unused)]
 #[derive(Debug, Snafu)]
 enum AppError {
     #[snafu(display("Failed to create project '{name}' at '{}': {source}", path.display()))]
@@ -51,6 +91,21 @@ enum AppError {
     #[snafu(display("Failed to serve project at '{}': {source}", path.display()))]
     Serve { source: io::Error, path: PathBuf },
 
+    #[snafu(display("Failed to list the posts of the project at '{}': {source}", path.display()))]
+    List {
+        source: Box<build::BuildError>,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Failed to generate the highlight stylesheet: {source}"))]
+    HighlightCss { source: Box<build::BuildError> },
+
+    #[snafu(display("Failed to write the highlight stylesheet to '{}': {source}", path.display()))]
+    WriteHighlightCss { source: io::Error, path: PathBuf },
+
+    #[snafu(display("No helix theme was given, and the project doesn't set `[highlight] theme`"))]
+    MissingTheme,
+
     #[snafu(display("Failed to get current directory: {source}"))]
     CurrentDir { source: io::Error },
 }
@@ -77,10 +132,49 @@ fn run() -> AppRes {
                 path: dir,
             }
         }),
-        Command::Build => build::build_proj(&dir).map_err(|source| AppError::Build {
-            source: Box::new(source),
-            path: dir,
-        }),
-        Command::Serve => todo!(),
+        Command::Build { drafts, future } => {
+            build::build_proj(&dir, &BuildOptions { drafts, future }).map_err(|source| {
+                AppError::Build {
+                    source: Box::new(source),
+                    path: dir,
+                }
+            })
+        }
+        Command::Serve { address, port } => serve::serve(&dir, SocketAddr::new(address, port))
+            .map_err(|source| AppError::Serve { source, path: dir }),
+        Command::List { scheduled } => {
+            build::list_posts(&dir, scheduled).map_err(|source| AppError::List {
+                source: Box::new(source),
+                path: dir,
+            })
+        }
+        Command::HighlightCss {
+            theme,
+            light,
+            dark,
+            output,
+        } => {
+            let theme = match (light.or(theme.clone()), dark.or(theme)) {
+                (Some(light), Some(dark)) => HighlightTheme::Variants { light, dark },
+                (None, None) => build::read_config(&dir)
+                    .ok()
+                    .and_then(|config| config.highlight.theme)
+                    .ok_or(AppError::MissingTheme)?,
+                _ => return Err(AppError::MissingTheme),
+            };
+            let css =
+                build::highlight_css(&dir, &theme).map_err(|source| AppError::HighlightCss {
+                    source: Box::new(source),
+                })?;
+            if output == Path::new("-") {
+                print!("{css}");
+                return Ok(());
+            }
+            let path = dir.join(output);
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&path, css))
+                .map_err(|source| AppError::WriteHighlightCss { source, path })
+        }
     }
 }
@@ -54,6 +54,7 @@ static DEFAULT_THEME: include_dir::Dir = include_dir!("$CARGO_MANIFEST_DIR/defau
 /// * Create the project directory
 /// * Create the `posts/` directory
 /// * Create the config file (`hyde.toml`)
+/// * Create a `.gitignore` for the highlight cache
 /// * Extract the embedded default theme into `default_theme/`
 pub fn new_project(
     dir: impl AsRef<Path>,
@@ -69,11 +70,17 @@ pub fn new_project(
     let mut config = File::options()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(config_path.clone())
         .context(OpenConfigSnafu)?;
     write_config(&mut config, name, display_name, desc)
         .context(MiscIOSnafu { path: config_path })?;
 
+    let gitignore_path = dir.join(".gitignore");
+    fs::write(&gitignore_path, ".hyde-cache/\n").context(MiscIOSnafu {
+        path: gitignore_path,
+    })?;
+
     DEFAULT_THEME
         .extract(dir.join("default_theme"))
         .context(ExtractThemeSnafu)?;
@@ -0,0 +1,271 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::TcpStream,
+    path::{Component, Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use super::{Clients, Message};
+
+/// The path that pages connect to in order to be told when to reload
+const LIVE_RELOAD_PATH: &str = "/__hyde/live-reload";
+
+/// The script injected into every HTML page, which reloads the page whenever the site is rebuilt,
+/// and shows an overlay whenever the build fails
+const LIVE_RELOAD_SCRIPT: &str = include_str!("live_reload.js");
+
+/// How often to send a comment down an idle live reload connection, so that we notice when the
+/// browser tab has been closed
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Handle a single HTTP request, serving either a file from `static_dir` or a live reload event
+/// stream
+pub(super) fn handle(
+    mut stream: TcpStream,
+    static_dir: &Path,
+    clients: &Clients,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // We don't care about any of the headers, but they still need to be read
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return respond(stream, "400 Bad Request", "text/plain", b"Bad Request"),
+    };
+    if method != "GET" && method != "HEAD" {
+        return respond(
+            stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Method Not Allowed",
+        );
+    }
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+
+    if path == LIVE_RELOAD_PATH {
+        return live_reload(stream, clients);
+    }
+
+    let file_path = match resolve(static_dir, path) {
+        Some(file_path) => file_path,
+        None => return not_found(stream),
+    };
+    let content = match fs::read(&file_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return not_found(stream),
+        Err(err) => return Err(err),
+    };
+
+    let content_type = content_type(&file_path);
+    let body = if content_type.starts_with("text/html") {
+        inject_script(content)
+    } else {
+        content
+    };
+    if method == "HEAD" {
+        // The headers are those that the body would have been sent with
+        write_head(&mut stream, "200 OK", content_type, body.len())?;
+        stream.flush()
+    } else {
+        respond(stream, "200 OK", content_type, &body)
+    }
+}
+
+/// Write a complete response to the stream
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write_head(&mut stream, status, content_type, body.len())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Write the status line and headers of a response with a body of `length` bytes
+fn write_head(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    length: usize,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {length}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n"
+    )
+}
+
+/// Respond with a page that still listens for live reload events, so that the page can be
+/// reloaded once it has been built, or show the error preventing it from being built
+fn not_found(stream: TcpStream) -> io::Result<()> {
+    let page = b"<!DOCTYPE html><html><body><h1>Not Found</h1></body></html>".to_vec();
+    respond(
+        stream,
+        "404 Not Found",
+        "text/html; charset=utf-8",
+        &inject_script(page),
+    )
+}
+
+/// Keep the connection open as a server-sent event stream, forwarding every message reported to
+/// `clients` until the browser tab goes away
+fn live_reload(mut stream: TcpStream, clients: &Clients) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let (sender, receiver) = mpsc::channel();
+    clients.register(sender);
+    loop {
+        let event = match receiver.recv_timeout(KEEP_ALIVE) {
+            Ok(Message::Reload) => "event: reload\ndata:\n\n".to_string(),
+            Ok(Message::Error(overlay)) => {
+                // Each line of the data needs its own field, they're joined back up by the browser
+                let mut event = "event: build-error\n".to_string();
+                for line in overlay.lines() {
+                    event.push_str("data: ");
+                    event.push_str(line);
+                    event.push('\n');
+                }
+                event.push('\n');
+                event
+            }
+            Err(RecvTimeoutError::Timeout) => ":\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        // The browser tab closing shows up as a write error, which just means we're done
+        if stream
+            .write_all(event.as_bytes())
+            .and_then(|_| stream.flush())
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Resolve the path of a request to a file in `static_dir`, using `index.html` for directories
+///
+/// Returns `None` if the path tries to escape `static_dir`.
+fn resolve(static_dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = percent_decode(path)?;
+    let mut file_path = static_dir.to_path_buf();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(component) => file_path.push(component),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    if file_path.is_dir() {
+        file_path.push("index.html");
+    }
+    Some(file_path)
+}
+
+/// Decode the `%XX` escapes in a URL path
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Insert the live reload script just before the closing `</body>` tag, or at the very end if
+/// there isn't one
+fn inject_script(html: Vec<u8>) -> Vec<u8> {
+    let script = format!("<script>{LIVE_RELOAD_SCRIPT}</script>");
+    let html = match String::from_utf8(html) {
+        Ok(html) => html,
+        Err(err) => return err.into_bytes(),
+    };
+    let index = html.rfind("</body>").unwrap_or(html.len());
+    let mut injected = String::with_capacity(html.len() + script.len());
+    injected.push_str(&html[..index]);
+    injected.push_str(&script);
+    injected.push_str(&html[index..]);
+    injected.into_bytes()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[test]
+fn decodes_percent_escapes() {
+    assert_eq!(percent_decode("/a%20b.html").as_deref(), Some("/a b.html"));
+    assert_eq!(percent_decode("/%2e%2E%2Fa").as_deref(), Some("/../a"));
+    assert_eq!(percent_decode("/%e2%9c%a8").as_deref(), Some("/\u{2728}"));
+    assert_eq!(percent_decode("/%zz"), None);
+    assert_eq!(percent_decode("/a%"), None);
+    assert_eq!(percent_decode("/a%2"), None);
+    assert_eq!(percent_decode("/%\u{e9}"), None);
+    // Not UTF-8
+    assert_eq!(percent_decode("/%ff"), None);
+}
+
+#[test]
+fn resolves_paths_within_the_static_dir() {
+    let static_dir = Path::new("/nonexistent/static");
+    let resolve = |path| resolve(static_dir, path);
+    assert_eq!(
+        resolve("/posts/a%20b.html"),
+        Some(static_dir.join("posts/a b.html"))
+    );
+    assert_eq!(resolve("/./a.html"), Some(static_dir.join("a.html")));
+    assert_eq!(
+        resolve("/posts%2Fa.html"),
+        Some(static_dir.join("posts/a.html"))
+    );
+    // A leading encoded slash doesn't make the path absolute
+    assert_eq!(
+        resolve("/%2Fetc/passwd"),
+        Some(static_dir.join("etc/passwd"))
+    );
+
+    for path in [
+        "/../secret",
+        "/posts/../../secret",
+        "/%2e%2e/secret",
+        "/posts/%2E%2E/%2e%2e/secret",
+        "/%2F..%2Fsecret",
+        "/..%2Fsecret",
+        "/%zz",
+        "/secret%",
+    ] {
+        assert_eq!(resolve(path), None, "{path}");
+    }
+}
@@ -0,0 +1,37 @@
+(() => {
+    const events = new EventSource("/__hyde/live-reload");
+    events.addEventListener("reload", () => location.reload());
+    events.addEventListener("build-error", (event) => {
+        let overlay = document.getElementById("hyde-error-overlay");
+        if (!overlay) {
+            overlay = document.createElement("div");
+            overlay.id = "hyde-error-overlay";
+            document.body.appendChild(overlay);
+        }
+        // A shadow root stops the page's own styles from leaking into the overlay
+        const root = overlay.shadowRoot || overlay.attachShadow({ mode: "open" });
+        root.innerHTML = `
+            <style>
+                :host {
+                    position: fixed;
+                    inset: 0;
+                    z-index: 2147483647;
+                    overflow: auto;
+                    padding: 2rem;
+                    background: #1e1e1e;
+                    color: #d4d4d4;
+                    font-family: sans-serif;
+                }
+                h1 { color: #f44747; margin-top: 0; }
+                .message { font-size: 1.2rem; }
+                .location { color: #9cdcfe; font-family: monospace; }
+                pre {
+                    padding: 1rem;
+                    overflow-x: auto;
+                    background: #141414;
+                    border-left: 4px solid #f44747;
+                }
+            </style>
+            ${event.data}`;
+    });
+})();
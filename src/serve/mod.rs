@@ -0,0 +1,127 @@
+//! Serving a Hyde project locally, rebuilding it whenever it changes
+
+mod http;
+mod overlay;
+mod watch;
+
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread,
+};
+
+use crate::build::{self, BuildError, BuildOptions};
+
+/// A message pushed to every open browser tab
+#[derive(Debug, Clone)]
+enum Message {
+    /// The site was rebuilt, so the page should be reloaded
+    Reload,
+    /// The site failed to build, so the page should be covered by an overlay containing this HTML
+    Error(String),
+}
+
+/// The live reload connections of every open browser tab
+#[derive(Clone, Default)]
+struct Clients {
+    state: Arc<Mutex<ClientsState>>,
+}
+
+#[derive(Default)]
+struct ClientsState {
+    senders: Vec<Sender<Message>>,
+    /// The overlay for the error that the most recent build failed with, if it failed
+    error: Option<String>,
+}
+
+impl Clients {
+    /// Register a new browser tab, which will receive messages through `sender`
+    ///
+    /// If the most recent build failed, the tab is sent the error straight away.
+    fn register(&self, sender: Sender<Message>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = &state.error {
+            if sender.send(Message::Error(error.clone())).is_err() {
+                return;
+            }
+        }
+        state.senders.push(sender);
+    }
+
+    /// Tell every browser tab about the result of a build
+    fn report(&self, result: Result<(), BuildError>) {
+        let mut state = self.state.lock().unwrap();
+        state.error = result.err().map(|err| overlay::render(&err));
+        let message = match &state.error {
+            Some(error) => Message::Error(error.clone()),
+            None => Message::Reload,
+        };
+        // Forget the tabs that have since been closed
+        state
+            .senders
+            .retain(|sender| sender.send(message.clone()).is_ok());
+    }
+}
+
+/// Builds and serves the Hyde project in a given directory
+///
+/// # Summary
+///
+/// Build the project, serve its `static/` directory over HTTP at `addr`, and watch the `posts/`
+/// directory, the `hyde.toml` config file and the theme directory for changes. Whenever something
+/// changes, the project is rebuilt and every open page is told to reload itself, or to show an
+/// overlay describing the error if the build failed.
+///
+/// # Errors
+///
+/// Errors that occur while building the project are reported without stopping the server, so
+/// only I/O errors from binding the address or setting up the file watcher are returned.
+pub fn serve(dir: impl AsRef<Path>, addr: SocketAddr) -> io::Result<()> {
+    let dir = dir.as_ref().to_path_buf();
+    let listener = TcpListener::bind(addr)?;
+    let clients = Clients::default();
+    clients.report(rebuild(&dir));
+
+    let watcher = watch::watch(dir.clone(), clients.clone())?;
+
+    println!(
+        "\x1b[32;1mServing\x1b[0m: http://{} (press Ctrl+C to stop)",
+        listener.local_addr()?
+    );
+
+    let static_dir = dir.join("static");
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("\x1b[31;1mError\x1b[0m: Failed to accept connection: {err}");
+                continue;
+            }
+        };
+        let (static_dir, clients) = (static_dir.clone(), clients.clone());
+        thread::spawn(move || {
+            if let Err(err) = http::handle(stream, &static_dir, &clients) {
+                eprintln!("\x1b[31;1mError\x1b[0m: Failed to handle request: {err}");
+            }
+        });
+    }
+
+    drop(watcher);
+    Ok(())
+}
+
+/// Build the project, drafts and future posts included, printing any error without bailing out
+fn rebuild(dir: &Path) -> Result<(), BuildError> {
+    let options = BuildOptions {
+        drafts: true,
+        future: true,
+    };
+    build::build_proj(dir, &options).inspect_err(|err| {
+        eprintln!(
+            "\x1b[31;1mError\x1b[0m: Failed to build project at '{}': {err}",
+            dir.display()
+        )
+    })
+}
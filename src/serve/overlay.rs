@@ -0,0 +1,83 @@
+use std::fs;
+
+use pulldown_cmark::escape::escape_html;
+
+use crate::build::BuildError;
+
+/// Render the contents of the overlay shown in place of the page when the site fails to build
+///
+/// The overlay contains the error message, the file and line that caused it, and the offending
+/// line of source, where each of those is known.
+pub(super) fn render(err: &BuildError) -> String {
+    let mut html = String::new();
+    html.push_str("<h1>Build failed</h1>\n<p class=\"message\">");
+    escape_html(&mut html, &err.to_string()).unwrap();
+    html.push_str("</p>\n");
+
+    if let Some(path) = err.path() {
+        html.push_str("<p class=\"location\">");
+        escape_html(&mut html, &path.display().to_string()).unwrap();
+        if let Some(line) = err.line() {
+            html.push_str(&format!(":{line}"));
+        }
+        html.push_str("</p>\n");
+    }
+
+    if let Some(snippet) = snippet(err) {
+        html.push_str("<pre>");
+        escape_html(&mut html, &snippet).unwrap();
+        html.push_str("</pre>\n");
+    }
+    html
+}
+
+/// Get the source that caused the error, if it can be found
+fn snippet(err: &BuildError) -> Option<String> {
+    match err {
+        // Template errors know exactly where in the template they happened
+        BuildError::CompileTemplate { source } => Some(format!("{source:#}")),
+        BuildError::RenderPost { source, .. } => Some(format!("{source:#}")),
+        _ => {
+            let line = err.line()?;
+            let source = fs::read_to_string(err.path()?).ok()?;
+            let text = source.lines().nth(line.checked_sub(1)?)?;
+            Some(format!("{line} | {text}"))
+        }
+    }
+}
+
+#[test]
+fn renders_errors() {
+    use std::path::PathBuf;
+
+    assert_eq!(
+        render(&BuildError::MissingConfig),
+        "<h1>Build failed</h1>\n\
+         <p class=\"message\">The directory is missing the `hyde.toml` config file</p>\n"
+    );
+
+    let path = PathBuf::from("posts/<a>.md");
+    assert_eq!(
+        render(&BuildError::MissingFrontmatter { path }),
+        "<h1>Build failed</h1>\n\
+         <p class=\"message\">Missing the frontmatter of the post at 'posts/&lt;a&gt;.md'</p>\n\
+         <p class=\"location\">posts/&lt;a&gt;.md</p>\n"
+    );
+
+    // The source is read to show the offending line
+    let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"));
+    let source = serde_json::from_str::<serde_json::Value>("\n{").unwrap_err();
+    let html = render(&BuildError::ParseJsonFrontmatter {
+        source,
+        path: path.clone(),
+    });
+    assert!(html.contains(&format!("{}:2</p>", path.display())));
+    assert!(html.ends_with("<pre>2 | name = &quot;hyde&quot;</pre>\n"));
+
+    // Errors that aren't at any position have no line
+    let source = serde_json::from_value::<u8>(serde_json::Value::Null).unwrap_err();
+    assert_eq!(source.line(), 0);
+    let html = render(&BuildError::ParseJsonFrontmatter { source, path });
+    assert!(!html.contains(":0</p>"));
+    assert!(!html.contains("<pre>"));
+}
@@ -0,0 +1,83 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::build;
+
+use super::{rebuild, Clients};
+
+/// How long to wait for further changes before rebuilding, so that an editor saving several files
+/// at once (or writing a single file in several steps) only causes a single rebuild
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Watch the project in `dir` for changes, rebuilding it and reporting the result to `clients`
+/// whenever something relevant changes.
+///
+/// The returned watcher stops watching once it is dropped.
+pub(super) fn watch(dir: PathBuf, clients: Clients) -> io::Result<RecommendedWatcher> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    // The whole project is watched rather than the individual directories, as the theme directory
+    // can be changed in the config while we're running
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .map_err(io::Error::other)?;
+
+    thread::spawn(move || rebuild_on_change(&dir, &receiver, &clients));
+    Ok(watcher)
+}
+
+fn rebuild_on_change(dir: &Path, receiver: &Receiver<notify::Result<Event>>, clients: &Clients) {
+    while let Ok(event) = receiver.recv() {
+        if !is_relevant(dir, event) {
+            continue;
+        }
+        // Swallow the rest of this burst of changes
+        while receiver.recv_timeout(DEBOUNCE).is_ok() {}
+
+        println!("\x1b[34;1mRebuilding\x1b[0m: Changes detected");
+        clients.report(rebuild(dir));
+    }
+}
+
+/// Whether a file system event should trigger a rebuild, i.e. whether it modified a post, the
+/// config or the theme
+fn is_relevant(dir: &Path, event: notify::Result<Event>) -> bool {
+    let event = match event {
+        Ok(event) => event,
+        Err(err) => {
+            eprintln!("\x1b[31;1mError\x1b[0m: Failed to watch project: {err}");
+            return false;
+        }
+    };
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+
+    let posts_dir = dir.join("posts");
+    let config_path = dir.join("hyde.toml");
+    // If the config is broken we may as well rebuild on any change, as the error will be reported
+    let config = build::read_config(dir).ok();
+    let theme_dir = config.as_ref().map(|config| dir.join(&config.theme));
+    let highlight_themes = config
+        .as_ref()
+        .and_then(|config| config.highlight.theme.as_ref())
+        .map(|theme| [dir.join(theme.light()), dir.join(theme.dark())]);
+
+    event.paths.iter().any(|path| {
+        path.starts_with(&posts_dir)
+            || *path == config_path
+            || theme_dir
+                .as_ref()
+                .is_none_or(|theme_dir| path.starts_with(theme_dir))
+            || highlight_themes
+                .as_ref()
+                .is_some_and(|themes| themes.contains(path))
+    })
+}